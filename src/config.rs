@@ -3,14 +3,98 @@ use serde::Deserialize;
 use std::{fs::File, io::Read, path::Path};
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
 pub struct TrackConfig {
   pub folder: String,
+  pub repair_on_open: bool,
+  /// number of track files kept open, should stay well below
+  /// the open files limit of the process. The default leaves room below
+  /// the common soft limit of 1024 for connections and the store's own files.
+  pub cache_size: usize,
+  /// recount tracks and points in the background after startup
+  pub reconcile_on_start: bool,
+  /// points up to this many ms older than the latest point of their track
+  /// are inserted in order, older ones are dropped
+  pub reorder_window: u64,
+  pub dedup: DedupConfig,
+  pub retention: RetentionConfig,
 }
 
 impl Default for TrackConfig {
   fn default() -> Self {
     Self {
       folder: "/var/lib/tracks".into(),
+      repair_on_open: false,
+      cache_size: 512,
+      reconcile_on_start: false,
+      reorder_window: 0,
+      dedup: Default::default(),
+      retention: Default::default(),
+    }
+  }
+}
+
+/// Collapsing of stationary periods into plateaus. The tolerances are the
+/// largest differences of points still taken as equal, zero requires the
+/// values to be equal.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct DedupConfig {
+  /// degrees
+  pub lat: f64,
+  /// degrees
+  pub lng: f64,
+  /// ft
+  pub alt: i32,
+  /// degrees
+  pub hdg: i32,
+  /// kt
+  pub gs: i32,
+  /// ft/min
+  pub vs: i32,
+  /// seconds, a plateau keeps a point at least this often
+  pub heartbeat: Option<u64>,
+  /// seconds, a plateau is never extended over a silence longer than this
+  /// so that a disconnection stays visible as a gap between points, can't
+  /// be below the heartbeat
+  pub max_gap: Option<u64>,
+}
+
+impl DedupConfig {
+  pub fn validate(&self) -> Result<(), String> {
+    match (self.heartbeat, self.max_gap) {
+      (Some(heartbeat), Some(max_gap)) if max_gap < heartbeat => Err(format!(
+        "tracks.dedup.max_gap ({max_gap}) is below tracks.dedup.heartbeat ({heartbeat})"
+      )),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+  /// tracks not updated for this many seconds are removed
+  pub max_age: Option<u64>,
+  /// when track files take more than this many bytes the least recently
+  /// updated tracks are removed
+  pub max_size: Option<u64>,
+  /// seconds between expiry runs
+  pub interval: u64,
+}
+
+impl RetentionConfig {
+  pub fn is_enabled(&self) -> bool {
+    self.max_age.is_some() || self.max_size.is_some()
+  }
+}
+
+impl Default for RetentionConfig {
+  fn default() -> Self {
+    Self {
+      max_age: None,
+      max_size: None,
+      interval: 3600,
     }
   }
 }
@@ -77,7 +161,12 @@ pub fn read_in_config(filename: Option<&str>) -> Config {
         println!("error parsing config file {}: {}", fname, err);
         continue;
       }
-      return res.unwrap();
+      let config = res.unwrap();
+      if let Err(err) = config.tracks.dedup.validate() {
+        println!("error in config file {}: {}", fname, err);
+        continue;
+      }
+      return config;
     }
     println!("config file {} does not exist", fname);
   }
@@ -7,8 +7,13 @@ use tracked::{
   web::{
     error::{catch404, catch500},
     routes::{
+      admin::reconcile,
+      snapshot::get_snapshot,
       stats::get_metrics,
-      tracks::{show_track, show_track_compact, update_tracks},
+      tracks::{
+        delete_track, list_tracks, search_tracks, show_track, show_track_compact, truncate_track,
+        truncate_tracks, update_tracks, verify_track,
+      },
     },
   },
 };
@@ -39,8 +44,20 @@ async fn rocket() -> _ {
     .manage(m)
     .mount(
       "/api/v1/tracks",
-      routes![update_tracks, show_track, show_track_compact],
+      routes![
+        update_tracks,
+        list_tracks,
+        search_tracks,
+        show_track,
+        show_track_compact,
+        verify_track,
+        delete_track,
+        truncate_track,
+        truncate_tracks
+      ],
     )
+    .mount("/api/v1/snapshot", routes![get_snapshot])
+    .mount("/api/v1/admin", routes![reconcile])
     .mount("/", routes![get_metrics])
     .register("/", catchers![catch404, catch500])
 }
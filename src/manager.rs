@@ -1,8 +1,21 @@
-use crate::{config::Config, track::store::TrackStore};
-use tokio::sync::RwLock;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use log::{error, info};
+
+use crate::{
+  config::{Config, RetentionConfig},
+  track::{asyncstore::AsyncTrackStore, store::TrackStore},
+};
 
 pub struct Manager {
-  pub store: RwLock<TrackStore>,
+  pub store: AsyncTrackStore,
+  reconciling: Arc<AtomicBool>,
 }
 
 impl Manager {
@@ -12,8 +25,58 @@ impl Manager {
       panic!("can't create track store: {err}")
     }
 
-    Self {
-      store: RwLock::new(res.unwrap()),
+    let store = AsyncTrackStore::new(res.unwrap());
+    if cfg.tracks.retention.is_enabled() {
+      tokio::spawn(retention_task(store.clone(), cfg.tracks.retention));
+    }
+    let manager = Self {
+      store,
+      reconciling: Arc::new(AtomicBool::new(false)),
+    };
+    if cfg.tracks.reconcile_on_start {
+      manager.start_reconcile();
+    }
+    manager
+  }
+
+  /// Starts recounting the tracks in the background, returns false if a
+  /// recount is already running
+  pub fn start_reconcile(&self) -> bool {
+    if self.reconciling.swap(true, Ordering::Relaxed) {
+      return false;
+    }
+    tokio::spawn(reconcile_task(self.store.clone(), self.reconciling.clone()));
+    true
+  }
+}
+
+async fn reconcile_task(store: AsyncTrackStore, reconciling: Arc<AtomicBool>) {
+  let res = store.reconcile().await;
+  reconciling.store(false, Ordering::Relaxed);
+  match res {
+    Ok(report) => info!(
+      "reconcile: {} tracks with {} points found (were {} and {}), {} corrupt files",
+      report.inspection.track_count,
+      report.inspection.point_count,
+      report.previous.track_count,
+      report.previous.point_count,
+      report.inspection.corrupt.len()
+    ),
+    Err(err) => error!("reconcile: error recounting tracks: {err}"),
+  }
+}
+
+async fn retention_task(store: AsyncTrackStore, retention: RetentionConfig) {
+  let mut interval = tokio::time::interval(Duration::from_secs(retention.interval.max(1)));
+  loop {
+    interval.tick().await;
+    match store.expire_tracks(retention.clone()).await {
+      Ok(report) if report.removed_tracks > 0 => info!(
+        "retention: {} tracks with {} points removed, {} bytes freed",
+        report.removed_tracks, report.removed_points, report.removed_bytes
+      ),
+      Ok(_) => (),
+      Err(err) => error!("retention: error expiring tracks: {err}"),
     }
   }
 }
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use tokio::task::spawn_blocking;
+
+use crate::config::RetentionConfig;
+
+use super::{
+  cache::CacheStats,
+  entry::{TrackPoint, TrackPointCompact},
+  error::{MetaFileError, TrackFileError},
+  index::TrackSummary,
+  metafile::MetaBlock,
+  simplify::Simplification,
+  spatial::{BoundingBox, TrackCrossing},
+  store::{IngestStats, ReconcileReport, RetentionReport, TrackStore},
+  trackfile::PointOutcome,
+  trackmeta::TrackMeta,
+};
+
+/// Async facade of `TrackStore`. Track files are accessed with blocking
+/// syscalls so every operation is run on the tokio blocking pool instead
+/// of the runtime worker threads.
+#[derive(Clone)]
+pub struct AsyncTrackStore {
+  store: Arc<TrackStore>,
+}
+
+impl AsyncTrackStore {
+  pub fn new(store: TrackStore) -> Self {
+    Self {
+      store: Arc::new(store),
+    }
+  }
+
+  async fn run<F, T, E>(&self, f: F) -> Result<T, E>
+  where
+    F: FnOnce(&TrackStore) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: From<std::io::Error> + Send + 'static,
+  {
+    let store = self.store.clone();
+    spawn_blocking(move || f(&store))
+      .await
+      .map_err(std::io::Error::other)?
+  }
+
+  pub async fn append_batch(
+    &self,
+    entries: Vec<(String, TrackPoint)>,
+  ) -> Result<Vec<PointOutcome>, TrackFileError> {
+    self
+      .run(move |store| {
+        let entries: Vec<(&str, &TrackPoint)> = entries
+          .iter()
+          .map(|(track_id, point)| (track_id.as_str(), point))
+          .collect();
+        store.append_batch(&entries)
+      })
+      .await
+  }
+
+  pub async fn load_track(
+    &self,
+    track_id: &str,
+    interpolate: bool,
+    after: Option<i64>,
+    before: Option<i64>,
+    simplification: Option<Simplification>,
+  ) -> Result<Vec<TrackPoint>, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self
+      .run(move |store| store.load_track(&track_id, interpolate, after, before, simplification))
+      .await
+  }
+
+  pub async fn load_track_compact(
+    &self,
+    track_id: &str,
+    interpolate: bool,
+    after: Option<i64>,
+    before: Option<i64>,
+    simplification: Option<Simplification>,
+  ) -> Result<Vec<TrackPointCompact>, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self
+      .run(move |store| {
+        store.load_track_compact(&track_id, interpolate, after, before, simplification)
+      })
+      .await
+  }
+
+  pub async fn list_tracks(
+    &self,
+    prefix: Option<String>,
+    updated_since: Option<u64>,
+    limit: usize,
+    cursor: Option<String>,
+  ) -> Result<(Vec<TrackSummary>, Option<String>), TrackFileError> {
+    self
+      .run(move |store| {
+        Ok(store.list_tracks(prefix.as_deref(), updated_since, limit, cursor.as_deref()))
+      })
+      .await
+  }
+
+  pub async fn search_tracks(
+    &self,
+    bbox: BoundingBox,
+    after: i64,
+    before: i64,
+  ) -> Result<Vec<TrackCrossing>, TrackFileError> {
+    self
+      .run(move |store| store.search_tracks(&bbox, after, before))
+      .await
+  }
+
+  pub async fn snapshot(&self, ts: i64) -> Result<Vec<(String, TrackPoint)>, TrackFileError> {
+    self.run(move |store| store.snapshot(ts)).await
+  }
+
+  pub async fn load_track_meta(&self, track_id: &str) -> Result<TrackMeta, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self
+      .run(move |store| store.load_track_meta(&track_id))
+      .await
+  }
+
+  pub async fn update_track_meta(
+    &self,
+    track_id: &str,
+    update: TrackMeta,
+  ) -> Result<TrackMeta, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self
+      .run(move |store| store.update_track_meta(&track_id, &update))
+      .await
+  }
+
+  pub async fn verify_track(&self, track_id: &str) -> Result<Option<usize>, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self.run(move |store| store.verify_track(&track_id)).await
+  }
+
+  pub async fn remove_track(&self, track_id: &str) -> Result<(), TrackFileError> {
+    let track_id = track_id.to_owned();
+    self.run(move |store| store.remove_track(&track_id)).await
+  }
+
+  pub async fn truncate_track(&self, track_id: &str, after: i64) -> Result<u64, TrackFileError> {
+    let track_id = track_id.to_owned();
+    self
+      .run(move |store| store.truncate_track(&track_id, after))
+      .await
+  }
+
+  pub async fn truncate_tracks(
+    &self,
+    since: u64,
+    after: i64,
+  ) -> Result<(u64, u64), TrackFileError> {
+    self
+      .run(move |store| store.truncate_tracks(since, after))
+      .await
+  }
+
+  pub async fn expire_tracks(
+    &self,
+    retention: RetentionConfig,
+  ) -> Result<RetentionReport, TrackFileError> {
+    self.run(move |store| store.expire_tracks(&retention)).await
+  }
+
+  pub async fn reconcile(&self) -> Result<ReconcileReport, MetaFileError> {
+    self.run(|store| store.reconcile()).await
+  }
+
+  pub async fn get_metablock(&self) -> Result<MetaBlock, MetaFileError> {
+    self.run(|store| store.get_metablock()).await
+  }
+
+  pub fn cache_stats(&self) -> CacheStats {
+    self.store.cache_stats()
+  }
+
+  pub fn ingest_stats(&self) -> IngestStats {
+    self.store.ingest_stats()
+  }
+}
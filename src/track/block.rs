@@ -0,0 +1,385 @@
+use super::{codec::Reader, entry::TrackPoint, error::TrackFileError};
+
+/// Maximum number of points stored in a single block
+pub const BLOCK_POINTS: usize = 128;
+/// Size of an encoded block header in bytes
+pub const BLOCK_HEADER_SIZE: usize = 32;
+/// Size of the CRC32 trailing the payload
+pub const BLOCK_CHECKSUM_SIZE: usize = 4;
+
+const LATLNG_SCALE: f64 = 1e7;
+
+const EXT_VS: u8 = 1;
+const EXT_SQUAWK: u8 = 2;
+const EXT_ON_GROUND: u8 = 4;
+const EXT_QNH: u8 = 8;
+
+/// Location and summary of a block within a version 2 track file.
+///
+/// A block consists of a fixed-size header (point count, payload length,
+/// index of the first point within the track, first and last timestamps)
+/// followed by the payload where every point is
+/// stored as zigzag varint deltas against the previous one, followed by a
+/// mask of the extended fields present and their deltas. The payload ends
+/// with a CRC32 of the whole block, which is included in `len`.
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+  pub offset: u64,
+  pub start: usize,
+  pub count: usize,
+  pub len: usize,
+  pub first_ts: i64,
+  pub last_ts: i64,
+}
+
+impl BlockInfo {
+  pub fn end(&self) -> u64 {
+    self.offset + (BLOCK_HEADER_SIZE + self.len) as u64
+  }
+
+  pub fn end_idx(&self) -> usize {
+    self.start + self.count
+  }
+}
+
+fn zigzag(v: i64) -> u64 {
+  ((v << 1) ^ (v >> 63)) as u64
+}
+
+fn unzigzag(v: u64) -> i64 {
+  ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn put_varint(buf: &mut Vec<u8>, v: i64) {
+  let mut v = zigzag(v);
+  while v >= 0x80 {
+    buf.push((v as u8) | 0x80);
+    v >>= 7;
+  }
+  buf.push(v as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> Option<i64> {
+  let mut res: u64 = 0;
+  let mut shift = 0;
+  while shift < 64 {
+    let byte = *data.get(*pos)?;
+    *pos += 1;
+    res |= ((byte & 0x7f) as u64) << shift;
+    if byte & 0x80 == 0 {
+      return Some(unzigzag(res));
+    }
+    shift += 7;
+  }
+  None
+}
+
+fn scale(v: f64) -> i64 {
+  (v * LATLNG_SCALE).round() as i64
+}
+
+fn unscale(v: i64) -> f64 {
+  v as f64 / LATLNG_SCALE
+}
+
+/// Rounds the coordinates to the precision stored in a block so that
+/// comparisons against already stored points are exact
+pub fn quantize(point: &TrackPoint) -> TrackPoint {
+  TrackPoint {
+    lat: unscale(scale(point.lat)),
+    lng: unscale(scale(point.lng)),
+    ..point.clone()
+  }
+}
+
+fn fields(point: &TrackPoint) -> [i64; 6] {
+  [
+    point.ts,
+    scale(point.lat),
+    scale(point.lng),
+    point.hdg as i64,
+    point.gs as i64,
+    point.alt as i64,
+  ]
+}
+
+fn ext_fields(point: &TrackPoint) -> [(u8, Option<i64>); 4] {
+  [
+    (EXT_VS, point.vs.map(i64::from)),
+    (EXT_SQUAWK, point.squawk.map(i64::from)),
+    (EXT_ON_GROUND, point.on_ground.map(i64::from)),
+    (EXT_QNH, point.qnh.map(i64::from)),
+  ]
+}
+
+// extended fields are stored as deltas against the last point having them
+fn put_ext(buf: &mut Vec<u8>, point: &TrackPoint, prev: &mut [i64; 4]) {
+  let ext = ext_fields(point);
+  let mask = ext
+    .iter()
+    .filter(|(_, v)| v.is_some())
+    .fold(0, |mask, (bit, _)| mask | bit);
+  buf.push(mask);
+  for ((_, v), p) in ext.iter().zip(prev.iter_mut()) {
+    if let Some(v) = v {
+      put_varint(buf, v.wrapping_sub(*p));
+      *p = *v;
+    }
+  }
+}
+
+fn get_ext(
+  data: &[u8],
+  pos: &mut usize,
+  point: &mut TrackPoint,
+  prev: &mut [i64; 4],
+) -> Option<()> {
+  let mask = *data.get(*pos)?;
+  *pos += 1;
+  let mut values = [None; 4];
+  for (bit, (v, p)) in [EXT_VS, EXT_SQUAWK, EXT_ON_GROUND, EXT_QNH]
+    .iter()
+    .zip(values.iter_mut().zip(prev.iter_mut()))
+  {
+    if mask & bit != 0 {
+      *p = p.wrapping_add(get_varint(data, pos)?);
+      *v = Some(*p);
+    }
+  }
+  let [vs, squawk, on_ground, qnh] = values;
+  point.vs = vs.map(|v| v as i32);
+  point.squawk = squawk.map(|v| v as u16);
+  point.on_ground = on_ground.map(|v| v != 0);
+  point.qnh = qnh.map(|v| v as i32);
+  Some(())
+}
+
+/// Encodes the points as a block, `start` is the index of the first one
+/// within the track
+pub fn encode_block(points: &[TrackPoint], start: usize) -> Vec<u8> {
+  let mut payload = vec![];
+  let mut prev = [0i64; 6];
+  let mut prev_ext = [0i64; 4];
+  for point in points {
+    let curr = fields(point);
+    for (c, p) in curr.iter().zip(prev.iter()) {
+      put_varint(&mut payload, c.wrapping_sub(*p));
+    }
+    put_ext(&mut payload, point, &mut prev_ext);
+    prev = curr;
+  }
+
+  let first_ts = points.first().map(|p| p.ts).unwrap_or(0);
+  let last_ts = points.last().map(|p| p.ts).unwrap_or(0);
+  let len = payload.len() + BLOCK_CHECKSUM_SIZE;
+
+  let mut buf = Vec::with_capacity(BLOCK_HEADER_SIZE + len);
+  buf.extend_from_slice(&(points.len() as u32).to_le_bytes());
+  buf.extend_from_slice(&(len as u32).to_le_bytes());
+  buf.extend_from_slice(&(start as u64).to_le_bytes());
+  buf.extend_from_slice(&first_ts.to_le_bytes());
+  buf.extend_from_slice(&last_ts.to_le_bytes());
+  buf.extend_from_slice(&payload);
+  let crc = crc32fast::hash(&buf);
+  buf.extend_from_slice(&crc.to_le_bytes());
+  buf
+}
+
+/// Parses a block header located at `offset`
+pub fn decode_block_header(data: &[u8], offset: u64) -> Result<BlockInfo, TrackFileError> {
+  let mut reader = Reader::new(data, "block header");
+  let count = reader.u32()? as usize;
+  let len = reader.u32()? as usize;
+  let start = reader.u64()? as usize;
+  let first_ts = reader.i64()?;
+  let last_ts = reader.i64()?;
+  if count == 0 || count > BLOCK_POINTS || first_ts > last_ts || start.checked_add(count).is_none()
+  {
+    return Err(TrackFileError::InvalidBlock(offset));
+  }
+  Ok(BlockInfo {
+    offset,
+    start,
+    count,
+    len,
+    first_ts,
+    last_ts,
+  })
+}
+
+/// Decodes the points of a block, `data` must contain the entire block
+/// including its header
+pub fn decode_block(info: &BlockInfo, data: &[u8]) -> Result<Vec<TrackPoint>, TrackFileError> {
+  if data.len() < BLOCK_HEADER_SIZE + BLOCK_CHECKSUM_SIZE {
+    return Err(TrackFileError::InvalidBlock(info.offset));
+  }
+  let (body, crc) = data.split_at(data.len() - BLOCK_CHECKSUM_SIZE);
+  if crc32fast::hash(body).to_le_bytes() != crc {
+    return Err(TrackFileError::ChecksumMismatch(info.start));
+  }
+  let payload = &body[BLOCK_HEADER_SIZE..];
+
+  let mut points = Vec::with_capacity(info.count);
+  let mut prev = [0i64; 6];
+  let mut prev_ext = [0i64; 4];
+  let mut pos = 0;
+  for _ in 0..info.count {
+    let mut curr = [0i64; 6];
+    for (c, p) in curr.iter_mut().zip(prev.iter()) {
+      let delta = get_varint(payload, &mut pos).ok_or(TrackFileError::InvalidBlock(info.offset))?;
+      *c = p.wrapping_add(delta);
+    }
+    let mut point = TrackPoint {
+      ts: curr[0],
+      lat: unscale(curr[1]),
+      lng: unscale(curr[2]),
+      hdg: curr[3] as i32,
+      gs: curr[4] as i32,
+      alt: curr[5] as i32,
+      ..Default::default()
+    };
+    get_ext(payload, &mut pos, &mut point, &mut prev_ext)
+      .ok_or(TrackFileError::InvalidBlock(info.offset))?;
+    points.push(point);
+    prev = curr;
+  }
+  if pos != payload.len()
+    || points.first().map(|p| p.ts) != Some(info.first_ts)
+    || points.last().map(|p| p.ts) != Some(info.last_ts)
+  {
+    return Err(TrackFileError::InvalidBlock(info.offset));
+  }
+  Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(ts: i64) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat: 51.4775 + ts as f64 * 1e-5,
+      lng: -0.461389 - ts as f64 * 1e-5,
+      hdg: (ts % 360) as i32,
+      gs: 140,
+      alt: 3000 - ts as i32,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn zigzag_round_trip() {
+    assert_eq!(zigzag(0), 0);
+    assert_eq!(zigzag(-1), 1);
+    assert_eq!(zigzag(1), 2);
+    assert_eq!(zigzag(i64::MIN), u64::MAX);
+    for v in [0, 1, -1, 63, -64, 1 << 40, i64::MIN, i64::MAX] {
+      assert_eq!(unzigzag(zigzag(v)), v);
+    }
+  }
+
+  #[test]
+  fn varint_round_trip() {
+    let values = [0, 1, -1, 63, 64, -65, 300, -300, i64::MIN, i64::MAX];
+    let mut buf = vec![];
+    for v in values {
+      put_varint(&mut buf, v);
+    }
+    // small deltas take a single byte
+    assert_eq!(buf[0], 0);
+    assert_eq!(buf[1], 2);
+
+    let mut pos = 0;
+    for v in values {
+      assert_eq!(get_varint(&buf, &mut pos), Some(v));
+    }
+    assert_eq!(pos, buf.len());
+    assert_eq!(get_varint(&buf, &mut pos), None);
+  }
+
+  #[test]
+  fn varint_rejects_truncated_and_overlong() {
+    let mut buf = vec![];
+    put_varint(&mut buf, i64::MAX);
+    let mut pos = 0;
+    assert_eq!(get_varint(&buf[..buf.len() - 1], &mut pos), None);
+
+    let mut pos = 0;
+    assert_eq!(get_varint(&[0xff; 11], &mut pos), None);
+  }
+
+  #[test]
+  fn block_round_trip() {
+    let mut points: Vec<TrackPoint> = (0..BLOCK_POINTS as i64).map(|i| point(i * 1000)).collect();
+    points[3].vs = Some(-800);
+    points[3].squawk = Some(7700);
+    points[4].on_ground = Some(true);
+    points[5].qnh = Some(1013);
+    points[6].vs = Some(500);
+    let expected: Vec<TrackPoint> = points.iter().map(quantize).collect();
+
+    let data = encode_block(&points, 256);
+    let info = decode_block_header(&data, 32).unwrap();
+    assert_eq!(info.offset, 32);
+    assert_eq!(info.start, 256);
+    assert_eq!(info.count, BLOCK_POINTS);
+    assert_eq!(info.end_idx(), 256 + BLOCK_POINTS);
+    assert_eq!(info.first_ts, 0);
+    assert_eq!(info.last_ts, (BLOCK_POINTS as i64 - 1) * 1000);
+    assert_eq!(info.end(), 32 + data.len() as u64);
+
+    let decoded = decode_block(&info, &data).unwrap();
+    assert_eq!(decoded.len(), expected.len());
+    for (decoded, expected) in decoded.iter().zip(expected.iter()) {
+      assert_eq!(decoded.ts, expected.ts);
+      assert_eq!(decoded, expected);
+    }
+  }
+
+  #[test]
+  fn quantize_is_stable() {
+    let p = quantize(&point(12345));
+    let data = encode_block(std::slice::from_ref(&p), 0);
+    let info = decode_block_header(&data, 0).unwrap();
+    assert_eq!(decode_block(&info, &data).unwrap()[0], p);
+    assert_eq!(quantize(&p), p);
+  }
+
+  #[test]
+  fn block_detects_corruption() {
+    let points: Vec<TrackPoint> = (0..10).map(point).collect();
+    let mut data = encode_block(&points, 0);
+    let info = decode_block_header(&data, 0).unwrap();
+    data[BLOCK_HEADER_SIZE + 3] ^= 0x10;
+    assert!(matches!(
+      decode_block(&info, &data),
+      Err(TrackFileError::ChecksumMismatch(0))
+    ));
+    assert!(matches!(
+      decode_block(&info, &data[..BLOCK_HEADER_SIZE]),
+      Err(TrackFileError::InvalidBlock(0))
+    ));
+  }
+
+  #[test]
+  fn block_header_rejects_invalid() {
+    let points: Vec<TrackPoint> = (0..10).map(point).collect();
+    let data = encode_block(&points, 0);
+
+    let mut empty = data.clone();
+    empty[..4].copy_from_slice(&0u32.to_le_bytes());
+    assert!(matches!(
+      decode_block_header(&empty, 64),
+      Err(TrackFileError::InvalidBlock(64))
+    ));
+
+    let mut unsorted = data.clone();
+    unsorted[16..24].copy_from_slice(&i64::MAX.to_le_bytes());
+    assert!(decode_block_header(&unsorted, 0).is_err());
+
+    let mut overflow = data;
+    overflow[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(decode_block_header(&overflow, 0).is_err());
+  }
+}
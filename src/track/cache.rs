@@ -0,0 +1,208 @@
+use std::{
+  collections::HashMap,
+  hash::{DefaultHasher, Hash, Hasher},
+  num::NonZeroUsize,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, RwLock, Weak,
+  },
+};
+
+use lru::LruCache;
+
+use super::{error::TrackFileError, trackfile::TrackFile};
+
+const SHARDS: usize = 16;
+
+pub type SharedTrackFile = Arc<RwLock<TrackFile>>;
+
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+  pub size: usize,
+  pub capacity: usize,
+  pub hits: u64,
+  pub misses: u64,
+}
+
+struct Shard {
+  lru: LruCache<String, SharedTrackFile>,
+  // handles evicted from the lru but still in use by someone, they must be
+  // reused as two handles of the same file would have diverging headers
+  live: HashMap<String, Weak<RwLock<TrackFile>>>,
+}
+
+/// Sharded LRU of open track files. Every handle is guarded by its own
+/// lock so that reads and writes of different tracks don't block each other.
+pub struct TrackCache {
+  shards: Vec<Mutex<Shard>>,
+  capacity: usize,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+impl TrackCache {
+  pub fn new(capacity: usize) -> Self {
+    let shard_capacity = NonZeroUsize::new(capacity.div_ceil(SHARDS)).unwrap_or(NonZeroUsize::MIN);
+    let shards = (0..SHARDS)
+      .map(|_| {
+        Mutex::new(Shard {
+          lru: LruCache::new(shard_capacity),
+          live: HashMap::new(),
+        })
+      })
+      .collect();
+    Self {
+      shards,
+      capacity: shard_capacity.get() * SHARDS,
+      hits: AtomicU64::new(0),
+      misses: AtomicU64::new(0),
+    }
+  }
+
+  fn shard(&self, track_id: &str) -> &Mutex<Shard> {
+    let mut hasher = DefaultHasher::new();
+    track_id.hash(&mut hasher);
+    &self.shards[hasher.finish() as usize % SHARDS]
+  }
+
+  /// Returns the cached handle of a track or calls `open` to get one. The
+  /// shard is locked while opening so a track is never opened twice.
+  pub fn get_or_open<F>(&self, track_id: &str, open: F) -> Result<SharedTrackFile, TrackFileError>
+  where
+    F: FnOnce() -> Result<TrackFile, TrackFileError>,
+  {
+    let mut shard = self.shard(track_id).lock().unwrap();
+    if let Some(tf) = shard.lru.get(track_id) {
+      self.hits.fetch_add(1, Ordering::Relaxed);
+      return Ok(tf.clone());
+    }
+
+    let tf = match shard.live.get(track_id).and_then(|tf| tf.upgrade()) {
+      Some(tf) => {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        tf
+      }
+      None => {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Arc::new(RwLock::new(open()?))
+      }
+    };
+
+    if let Some((evicted_id, evicted)) = shard.lru.push(track_id.to_owned(), tf.clone()) {
+      if evicted_id != track_id && Arc::strong_count(&evicted) > 1 {
+        shard.live.insert(evicted_id, Arc::downgrade(&evicted));
+      }
+    }
+    shard.live.remove(track_id);
+    if shard.live.len() > shard.lru.cap().get() {
+      shard.live.retain(|_, tf| tf.strong_count() > 0);
+    }
+    Ok(tf)
+  }
+
+  /// Drops the handle of a track from the cache and passes it, if there
+  /// was one, to `f`. The shard stays locked while `f` runs so the track
+  /// can't be reopened until it's done.
+  pub fn remove_with<F, T>(&self, track_id: &str, f: F) -> Result<T, TrackFileError>
+  where
+    F: FnOnce(Option<SharedTrackFile>) -> Result<T, TrackFileError>,
+  {
+    let mut shard = self.shard(track_id).lock().unwrap();
+    let tf = shard.lru.pop(track_id);
+    let live = shard.live.remove(track_id).and_then(|tf| tf.upgrade());
+    f(tf.or(live))
+  }
+
+  pub fn stats(&self) -> CacheStats {
+    let size = self
+      .shards
+      .iter()
+      .map(|shard| shard.lock().unwrap().lru.len())
+      .sum();
+    CacheStats {
+      size,
+      capacity: self.capacity,
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  struct TempDir(PathBuf);
+
+  impl TempDir {
+    fn new(name: &str) -> Self {
+      let path = std::env::temp_dir().join(format!("tracked-{}-{name}", std::process::id()));
+      let _ = std::fs::remove_dir_all(&path);
+      std::fs::create_dir_all(&path).unwrap();
+      Self(path)
+    }
+
+    fn open(&self, track_id: &str) -> Result<TrackFile, TrackFileError> {
+      TrackFile::create(self.0.join(format!("{track_id}.bin")))
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[test]
+  fn evicts_least_recently_used() {
+    let dir = TempDir::new("cache-evict");
+    let cache = TrackCache::new(SHARDS);
+    for i in 0..100 {
+      let track_id = format!("t{i}");
+      cache
+        .get_or_open(&track_id, || dir.open(&track_id))
+        .unwrap();
+    }
+    let stats = cache.stats();
+    assert_eq!(stats.capacity, SHARDS);
+    assert!(stats.size <= SHARDS);
+    assert_eq!((stats.hits, stats.misses), (0, 100));
+
+    // the last track is still cached, the first one was evicted
+    cache.get_or_open("t99", || panic!("t99 reopened")).unwrap();
+    let mut reopened = false;
+    cache
+      .get_or_open("t0", || {
+        reopened = true;
+        dir.open("t0")
+      })
+      .unwrap();
+    assert!(reopened);
+    assert_eq!((cache.stats().hits, cache.stats().misses), (1, 101));
+  }
+
+  #[test]
+  fn handles_in_use_are_reused() {
+    let dir = TempDir::new("cache-live");
+    let cache = TrackCache::new(SHARDS);
+    let held = cache.get_or_open("held", || dir.open("held")).unwrap();
+    for i in 0.. {
+      if !cache.shard("held").lock().unwrap().lru.contains("held") {
+        break;
+      }
+      let track_id = format!("t{i}");
+      cache
+        .get_or_open(&track_id, || dir.open(&track_id))
+        .unwrap();
+    }
+    let tf = cache
+      .get_or_open("held", || panic!("held reopened"))
+      .unwrap();
+    assert!(Arc::ptr_eq(&tf, &held));
+
+    let removed = cache.remove_with("held", Ok).unwrap().unwrap();
+    assert!(Arc::ptr_eq(&removed, &held));
+    assert!(cache.remove_with("held", Ok).unwrap().is_none());
+  }
+}
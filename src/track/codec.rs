@@ -0,0 +1,518 @@
+use super::{
+  entry::TrackPoint,
+  error::CodecError,
+  header::Header,
+  metafile::{MetaBlock, METABLOCK_VERSION},
+};
+
+/// Explicit little-endian on-disk representation of a fixed-size structure.
+///
+/// Layouts match the `#[repr(C)]` structs previously written as raw memory
+/// on x86_64, so files created by older versions remain readable.
+pub trait Codec: Sized {
+  const SIZE: usize;
+  const IDENT: &'static str;
+
+  fn encode_into(&self, buf: &mut Vec<u8>);
+  fn decode_from(reader: &mut Reader) -> Result<Self, CodecError>;
+
+  fn encode(&self) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(Self::SIZE);
+    self.encode_into(&mut buf);
+    buf
+  }
+
+  fn decode(data: &[u8]) -> Result<Self, CodecError> {
+    if data.len() < Self::SIZE {
+      return Err(CodecError::InsufficientDataLength(
+        Self::IDENT.into(),
+        data.len(),
+      ));
+    }
+    let mut reader = Reader::new(&data[..Self::SIZE], Self::IDENT);
+    Self::decode_from(&mut reader)
+  }
+}
+
+pub struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+  ident: &'static str,
+}
+
+impl<'a> Reader<'a> {
+  pub fn new(data: &'a [u8], ident: &'static str) -> Self {
+    Self {
+      data,
+      pos: 0,
+      ident,
+    }
+  }
+
+  pub fn pos(&self) -> usize {
+    self.pos
+  }
+
+  pub fn remaining(&self) -> usize {
+    self.data.len() - self.pos
+  }
+
+  /// Returns the data read since position `start`
+  pub fn consumed_since(&self, start: usize) -> &'a [u8] {
+    &self.data[start..self.pos]
+  }
+
+  fn take<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+    let end = self.pos + N;
+    if end > self.data.len() {
+      return Err(CodecError::InsufficientDataLength(
+        self.ident.into(),
+        self.data.len(),
+      ));
+    }
+    let bytes = self.data[self.pos..end].try_into().unwrap();
+    self.pos = end;
+    Ok(bytes)
+  }
+
+  pub fn skip(&mut self, len: usize) -> Result<(), CodecError> {
+    if self.pos + len > self.data.len() {
+      return Err(CodecError::InsufficientDataLength(
+        self.ident.into(),
+        self.data.len(),
+      ));
+    }
+    self.pos += len;
+    Ok(())
+  }
+
+  pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], CodecError> {
+    let start = self.pos;
+    self.skip(len)?;
+    Ok(&self.data[start..self.pos])
+  }
+
+  pub fn u8(&mut self) -> Result<u8, CodecError> {
+    Ok(self.take::<1>()?[0])
+  }
+
+  pub fn u16(&mut self) -> Result<u16, CodecError> {
+    Ok(u16::from_le_bytes(self.take()?))
+  }
+
+  pub fn u32(&mut self) -> Result<u32, CodecError> {
+    Ok(u32::from_le_bytes(self.take()?))
+  }
+
+  pub fn u64(&mut self) -> Result<u64, CodecError> {
+    Ok(u64::from_le_bytes(self.take()?))
+  }
+
+  pub fn i32(&mut self) -> Result<i32, CodecError> {
+    Ok(i32::from_le_bytes(self.take()?))
+  }
+
+  pub fn i64(&mut self) -> Result<i64, CodecError> {
+    Ok(i64::from_le_bytes(self.take()?))
+  }
+
+  pub fn f64(&mut self) -> Result<f64, CodecError> {
+    Ok(f64::from_le_bytes(self.take()?))
+  }
+}
+
+fn invalid(field: &str, reason: String) -> CodecError {
+  CodecError::InvalidField(field.into(), reason)
+}
+
+/// Checks that a point carries sane coordinates. Points are only checked
+/// when written, stored ones are decoded as they are and reported by
+/// `TrackFile::verify`.
+pub fn validate_point(point: &TrackPoint) -> Result<(), CodecError> {
+  if !point.lat.is_finite() || !(-90.0..=90.0).contains(&point.lat) {
+    return Err(invalid("lat", format!("{} is out of range", point.lat)));
+  }
+  if !point.lng.is_finite() || !(-180.0..=180.0).contains(&point.lng) {
+    return Err(invalid("lng", format!("{} is out of range", point.lng)));
+  }
+  if let Some(squawk) = point.squawk {
+    let octal = (0..4).all(|i| (squawk / 10u16.pow(i)) % 10 < 8);
+    if squawk > 7777 || !octal {
+      return Err(invalid("squawk", format!("{squawk} is not a valid code")));
+    }
+  }
+  Ok(())
+}
+
+/// Encodes the extended fields of a point, which the fixed-size record
+/// can't hold, as a presence mask followed by the present values
+pub fn encode_extended(point: &TrackPoint, buf: &mut Vec<u8>) {
+  let mask = [
+    point.vs.is_some(),
+    point.squawk.is_some(),
+    point.on_ground.is_some(),
+    point.qnh.is_some(),
+  ]
+  .iter()
+  .enumerate()
+  .fold(0u8, |mask, (i, present)| mask | ((*present as u8) << i));
+  buf.push(mask);
+  if let Some(vs) = point.vs {
+    buf.extend_from_slice(&vs.to_le_bytes());
+  }
+  if let Some(squawk) = point.squawk {
+    buf.extend_from_slice(&squawk.to_le_bytes());
+  }
+  if let Some(on_ground) = point.on_ground {
+    buf.push(on_ground as u8);
+  }
+  if let Some(qnh) = point.qnh {
+    buf.extend_from_slice(&qnh.to_le_bytes());
+  }
+}
+
+pub fn decode_extended(reader: &mut Reader, point: &mut TrackPoint) -> Result<(), CodecError> {
+  let mask = reader.u8()?;
+  if mask & 1 != 0 {
+    point.vs = Some(reader.i32()?);
+  }
+  if mask & 2 != 0 {
+    point.squawk = Some(reader.u16()?);
+  }
+  if mask & 4 != 0 {
+    point.on_ground = Some(reader.u8()? != 0);
+  }
+  if mask & 8 != 0 {
+    point.qnh = Some(reader.i32()?);
+  }
+  Ok(())
+}
+
+// Header checksum covers the header with the checksum field zeroed
+fn header_checksum(header: &Header) -> u32 {
+  let mut hasher = crc32fast::Hasher::new();
+  hasher.update(&header.magic.to_le_bytes());
+  hasher.update(&header.version.to_le_bytes());
+  hasher.update(&header.flags.to_le_bytes());
+  hasher.update(&[0; 4]);
+  hasher.update(&header.updated_at.to_le_bytes());
+  hasher.update(&header.count.to_le_bytes());
+  hasher.finalize()
+}
+
+impl Codec for Header {
+  // the version word of version 1 headers was a single u64, its upper
+  // bytes are always zero and now hold flags and the header checksum
+  const SIZE: usize = 32;
+  const IDENT: &'static str = "header";
+
+  fn encode_into(&self, buf: &mut Vec<u8>) {
+    let checksum = if self.has_checksums() {
+      header_checksum(self)
+    } else {
+      0
+    };
+    buf.extend_from_slice(&self.magic.to_le_bytes());
+    buf.extend_from_slice(&self.version.to_le_bytes());
+    buf.extend_from_slice(&self.flags.to_le_bytes());
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf.extend_from_slice(&self.updated_at.to_le_bytes());
+    buf.extend_from_slice(&self.count.to_le_bytes());
+  }
+
+  fn decode_from(reader: &mut Reader) -> Result<Self, CodecError> {
+    let magic = reader.u64()?;
+    let version = reader.u16()?;
+    let flags = reader.u16()?;
+    let checksum = reader.u32()?;
+    let header = Self {
+      magic,
+      version,
+      flags,
+      updated_at: reader.u64()?,
+      count: reader.u64()?,
+    };
+    if header.version == 0 {
+      return Err(invalid("header.version", "version can't be zero".into()));
+    }
+    if header.has_checksums() && header_checksum(&header) != checksum {
+      return Err(CodecError::ChecksumMismatch(Self::IDENT.into()));
+    }
+    Ok(header)
+  }
+}
+
+impl Codec for TrackPoint {
+  // 36 bytes of data padded to 8 byte alignment, extended fields are not
+  // part of the record
+  const SIZE: usize = 40;
+  const IDENT: &'static str = "track entry";
+
+  fn encode_into(&self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&self.ts.to_le_bytes());
+    buf.extend_from_slice(&self.lat.to_le_bytes());
+    buf.extend_from_slice(&self.lng.to_le_bytes());
+    buf.extend_from_slice(&self.hdg.to_le_bytes());
+    buf.extend_from_slice(&self.gs.to_le_bytes());
+    buf.extend_from_slice(&self.alt.to_le_bytes());
+    buf.extend_from_slice(&[0; 4]);
+  }
+
+  fn decode_from(reader: &mut Reader) -> Result<Self, CodecError> {
+    let point = Self {
+      ts: reader.i64()?,
+      lat: reader.f64()?,
+      lng: reader.f64()?,
+      hdg: reader.i32()?,
+      gs: reader.i32()?,
+      alt: reader.i32()?,
+      ..Default::default()
+    };
+    reader.skip(4)?;
+    Ok(point)
+  }
+}
+
+impl Codec for MetaBlock {
+  // version 1 fields come first so the layout stays compatible, a zero
+  // version word means the rest is absent
+  const SIZE: usize = 64;
+  const IDENT: &'static str = "metablock";
+
+  fn encode_into(&self, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&self.track_count.to_le_bytes());
+    buf.extend_from_slice(&self.point_count.to_le_bytes());
+    buf.extend_from_slice(&self.updated_at.to_le_bytes());
+    buf.extend_from_slice(&METABLOCK_VERSION.to_le_bytes());
+    buf.extend_from_slice(&self.total_bytes.to_le_bytes());
+    buf.extend_from_slice(&self.corrupt_count.to_le_bytes());
+    buf.extend_from_slice(&self.oldest_updated_at.to_le_bytes());
+    buf.extend_from_slice(&self.newest_updated_at.to_le_bytes());
+  }
+
+  fn decode_from(reader: &mut Reader) -> Result<Self, CodecError> {
+    let mut block = MetaBlock {
+      track_count: reader.u64()?,
+      point_count: reader.u64()?,
+      updated_at: reader.u64()?,
+      ..Default::default()
+    };
+    match reader.u64()? {
+      0 | 1 => reader.skip(32)?,
+      METABLOCK_VERSION => {
+        block.total_bytes = reader.u64()?;
+        block.corrupt_count = reader.u64()?;
+        block.oldest_updated_at = reader.u64()?;
+        block.newest_updated_at = reader.u64()?;
+      }
+      version => {
+        return Err(invalid(
+          "metablock.version",
+          format!("unsupported version {version}"),
+        ))
+      }
+    }
+    Ok(block)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::header::{VERSION_BLOCKS, VERSION_FLAT};
+
+  fn point() -> TrackPoint {
+    TrackPoint {
+      ts: 1_700_000_000_000,
+      lat: 40.6413,
+      lng: -73.7781,
+      hdg: 225,
+      gs: 160,
+      alt: 1200,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn header_round_trip() {
+    let header = Header {
+      count: 42,
+      ..Header::new().unwrap()
+    };
+    let data = header.encode();
+    assert_eq!(data.len(), Header::SIZE);
+    let decoded = Header::decode(&data).unwrap();
+    assert!(decoded.check_magic());
+    assert_eq!(decoded.version(), VERSION_BLOCKS);
+    assert!(decoded.has_checksums());
+    assert_eq!(decoded.count(), 42);
+    assert_eq!(decoded.timestamp(), header.timestamp());
+  }
+
+  #[test]
+  fn header_checksum_mismatch() {
+    let mut data = Header::new().unwrap().encode();
+    data[24] ^= 1;
+    assert!(matches!(
+      Header::decode(&data),
+      Err(CodecError::ChecksumMismatch(_))
+    ));
+  }
+
+  #[test]
+  fn flat_header_has_no_checksum() {
+    let header = Header {
+      version: VERSION_FLAT,
+      flags: 0,
+      ..Header::new().unwrap()
+    };
+    let mut data = header.encode();
+    // the count isn't covered by anything in version 1 headers
+    data[24] ^= 1;
+    assert_eq!(Header::decode(&data).unwrap().count(), 1);
+
+    data[8..10].copy_from_slice(&0u16.to_le_bytes());
+    assert!(Header::decode(&data).is_err());
+  }
+
+  #[test]
+  fn point_round_trip() {
+    let point = point();
+    let data = point.encode();
+    assert_eq!(data.len(), TrackPoint::SIZE);
+    let decoded = TrackPoint::decode(&data).unwrap();
+    assert_eq!(decoded.ts, point.ts);
+    assert_eq!(decoded, point);
+    assert!(TrackPoint::decode(&data[1..]).is_err());
+  }
+
+  #[test]
+  fn stored_points_are_not_validated() {
+    let point = TrackPoint {
+      lat: 200.0,
+      ..Default::default()
+    };
+    assert!(validate_point(&point).is_err());
+    let decoded = TrackPoint::decode(&point.encode()).unwrap();
+    assert_eq!(decoded.lat, 200.0);
+  }
+
+  #[test]
+  fn validate_point_ranges() {
+    assert!(validate_point(&point()).is_ok());
+    for (lat, lng) in [
+      (90.5, 0.0),
+      (0.0, -181.0),
+      (f64::NAN, 0.0),
+      (0.0, f64::INFINITY),
+    ] {
+      let point = TrackPoint {
+        lat,
+        lng,
+        ..Default::default()
+      };
+      assert!(validate_point(&point).is_err());
+    }
+  }
+
+  #[test]
+  fn extended_point_round_trip() {
+    let point = TrackPoint {
+      vs: Some(-700),
+      squawk: Some(1200),
+      on_ground: Some(false),
+      qnh: Some(1009),
+      ..point()
+    };
+    let mut buf = vec![];
+    point.encode_into(&mut buf);
+    assert_eq!(buf.len(), TrackPoint::SIZE);
+    encode_extended(&point, &mut buf);
+
+    let mut reader = Reader::new(&buf, "test");
+    let mut decoded = TrackPoint::decode_from(&mut reader).unwrap();
+    assert_eq!(decoded.vs, None);
+    decode_extended(&mut reader, &mut decoded).unwrap();
+    assert_eq!(reader.remaining(), 0);
+    assert_eq!(decoded.ts, point.ts);
+    assert_eq!(decoded, point);
+  }
+
+  #[test]
+  fn extended_fields_are_optional() {
+    let point = TrackPoint {
+      squawk: Some(7500),
+      ..Default::default()
+    };
+    let mut buf = vec![];
+    encode_extended(&point, &mut buf);
+    assert_eq!(buf, [2, 0x4c, 0x1d]);
+
+    let mut decoded = TrackPoint::default();
+    decode_extended(&mut Reader::new(&buf, "test"), &mut decoded).unwrap();
+    assert_eq!(decoded, point);
+  }
+
+  #[test]
+  fn validate_squawk() {
+    for (squawk, valid) in [
+      (7700, true),
+      (0, true),
+      (7777, true),
+      (7778, false),
+      (8000, false),
+    ] {
+      let point = TrackPoint {
+        squawk: Some(squawk),
+        ..Default::default()
+      };
+      assert_eq!(validate_point(&point).is_ok(), valid, "squawk {squawk}");
+    }
+  }
+
+  #[test]
+  fn metablock_round_trip() {
+    let block = MetaBlock {
+      track_count: 1,
+      point_count: 2,
+      updated_at: 3,
+      total_bytes: 4,
+      corrupt_count: 5,
+      oldest_updated_at: 6,
+      newest_updated_at: 7,
+    };
+    let data = block.encode();
+    assert_eq!(data.len(), MetaBlock::SIZE);
+    let decoded = MetaBlock::decode(&data).unwrap();
+    assert_eq!(
+      [
+        decoded.track_count,
+        decoded.point_count,
+        decoded.updated_at,
+        decoded.total_bytes,
+        decoded.corrupt_count,
+        decoded.oldest_updated_at,
+        decoded.newest_updated_at,
+      ],
+      [1, 2, 3, 4, 5, 6, 7]
+    );
+  }
+
+  #[test]
+  fn metablock_version_1() {
+    // version 1 blocks hold the counters and updated_at only, they are
+    // padded with zeros when read
+    let mut data = vec![0; MetaBlock::SIZE];
+    data[..8].copy_from_slice(&10u64.to_le_bytes());
+    data[8..16].copy_from_slice(&20u64.to_le_bytes());
+    data[16..24].copy_from_slice(&30u64.to_le_bytes());
+    let decoded = MetaBlock::decode(&data).unwrap();
+    assert_eq!(decoded.track_count, 10);
+    assert_eq!(decoded.point_count, 20);
+    assert_eq!(decoded.updated_at, 30);
+    assert_eq!(decoded.total_bytes, 0);
+
+    data[24..32].copy_from_slice(&99u64.to_le_bytes());
+    assert!(MetaBlock::decode(&data).is_err());
+  }
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::config::DedupConfig;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[repr(C)]
 pub struct TrackPoint {
   pub ts: i64,
   pub lat: f64,
@@ -9,6 +10,17 @@ pub struct TrackPoint {
   pub hdg: i32,
   pub gs: i32,
   pub alt: i32,
+  /// vertical speed, ft/min
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub vs: Option<i32>,
+  /// transponder code as its four octal digits, e.g. 7700
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub squawk: Option<u16>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub on_ground: Option<bool>,
+  /// QNH, hPa
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub qnh: Option<i32>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -29,6 +41,47 @@ pub struct TrackPointCompact {
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(rename = "a")]
   pub alt: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "v")]
+  pub vs: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "sq")]
+  pub squawk: Option<u16>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "og")]
+  pub on_ground: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "q")]
+  pub qnh: Option<i32>,
+}
+
+impl TrackPoint {
+  /// Whether the point has any of the fields which only version 2 track
+  /// files can store
+  pub fn has_extended(&self) -> bool {
+    self.vs.is_some() || self.squawk.is_some() || self.on_ground.is_some() || self.qnh.is_some()
+  }
+}
+
+impl TrackPoint {
+  /// Whether the points differ by no more than the tolerance, with the
+  /// fields having no tolerance compared exactly
+  pub fn is_near(&self, other: &Self, tolerance: &DedupConfig) -> bool {
+    let hdg = (self.hdg - other.hdg).rem_euclid(360);
+    let vs = match (self.vs, other.vs) {
+      (Some(a), Some(b)) => (a - b).abs() <= tolerance.vs,
+      (a, b) => a == b,
+    };
+    (self.lat - other.lat).abs() <= tolerance.lat
+      && (self.lng - other.lng).abs() <= tolerance.lng
+      && hdg.min(360 - hdg) <= tolerance.hdg
+      && (self.gs - other.gs).abs() <= tolerance.gs
+      && (self.alt - other.alt).abs() <= tolerance.alt
+      && vs
+      && self.squawk == other.squawk
+      && self.on_ground == other.on_ground
+      && self.qnh == other.qnh
+  }
 }
 
 impl PartialEq for TrackPoint {
@@ -38,5 +91,9 @@ impl PartialEq for TrackPoint {
       && self.hdg == other.hdg
       && self.gs == other.gs
       && self.alt == other.alt
+      && self.vs == other.vs
+      && self.squawk == other.squawk
+      && self.on_ground == other.on_ground
+      && self.qnh == other.qnh
   }
 }
@@ -7,8 +7,16 @@ pub enum TrackFileError {
   InvalidFileLength(usize, usize),
   InsufficientDataLength(String, usize),
   IndexError(usize),
-  SequenceError(i64),
   NotFound(String),
+  UnsupportedVersion(u64),
+  InvalidBlock(u64),
+  CountMismatch(u64, u64),
+  InvalidField(String, String),
+  InvalidPoint(i64, String),
+  HeaderChecksumMismatch,
+  ChecksumMismatch(usize),
+  InvalidMetadata(String, String),
+  InvalidTrackId(String),
 }
 
 impl Display for TrackFileError {
@@ -29,16 +37,56 @@ impl Display for TrackFileError {
       TrackFileError::NotFound(filename) => {
         write!(f, "Track file {filename} not found")
       }
-      TrackFileError::SequenceError(ts) => {
-        write!(
-          f,
-          "Can't append a point ts={ts}, a bigger ts exists in the track"
-        )
+      TrackFileError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported track file version {version}")
+      }
+      TrackFileError::InvalidBlock(offset) => {
+        write!(f, "Track file corrupted, invalid block at offset {offset}")
+      }
+      TrackFileError::CountMismatch(expected, got) => write!(
+        f,
+        "Invalid track file point count: header says {expected}, blocks contain {got}"
+      ),
+      TrackFileError::InvalidField(field, reason) => {
+        write!(f, "Invalid value of {field}: {reason}")
+      }
+      TrackFileError::InvalidPoint(ts, reason) => {
+        write!(f, "Can't append a point ts={ts}: {reason}")
+      }
+      TrackFileError::HeaderChecksumMismatch => {
+        write!(f, "Track file corrupted, header checksum mismatch")
+      }
+      TrackFileError::ChecksumMismatch(idx) => {
+        write!(f, "Track file corrupted, checksum mismatch at index {idx}")
+      }
+      TrackFileError::InvalidMetadata(filename, reason) => {
+        write!(f, "Invalid track metadata in {filename}: {reason}")
+      }
+      TrackFileError::InvalidTrackId(track_id) => {
+        write!(f, "Invalid track id {track_id:?}")
       }
     }
   }
 }
 
+impl TrackFileError {
+  /// Whether the error means the track file itself can't be read back
+  pub fn is_corruption(&self) -> bool {
+    matches!(
+      self,
+      TrackFileError::InvalidMagicNumber
+        | TrackFileError::InvalidFileLength(_, _)
+        | TrackFileError::InsufficientDataLength(_, _)
+        | TrackFileError::UnsupportedVersion(_)
+        | TrackFileError::InvalidBlock(_)
+        | TrackFileError::CountMismatch(_, _)
+        | TrackFileError::InvalidField(_, _)
+        | TrackFileError::HeaderChecksumMismatch
+        | TrackFileError::ChecksumMismatch(_)
+    )
+  }
+}
+
 impl Error for TrackFileError {}
 
 impl From<std::io::Error> for TrackFileError {
@@ -47,11 +95,22 @@ impl From<std::io::Error> for TrackFileError {
   }
 }
 
+impl From<CodecError> for TrackFileError {
+  fn from(value: CodecError) -> Self {
+    match value {
+      CodecError::InsufficientDataLength(ident, size) => Self::InsufficientDataLength(ident, size),
+      CodecError::InvalidField(field, reason) => Self::InvalidField(field, reason),
+      CodecError::ChecksumMismatch(_) => Self::HeaderChecksumMismatch,
+    }
+  }
+}
+
 #[derive(Debug)]
 pub enum MetaFileError {
   IOError(std::io::Error),
   InsufficientDataLength(String, usize),
   NotFound(String),
+  InvalidField(String, String),
 }
 
 impl Display for MetaFileError {
@@ -64,6 +123,9 @@ impl Display for MetaFileError {
       MetaFileError::NotFound(filename) => {
         write!(f, "Meta file {filename} not found")
       }
+      MetaFileError::InvalidField(field, reason) => {
+        write!(f, "Invalid value of {field}: {reason}")
+      }
     }
   }
 }
@@ -75,3 +137,38 @@ impl From<std::io::Error> for MetaFileError {
     Self::IOError(value)
   }
 }
+
+impl From<CodecError> for MetaFileError {
+  fn from(value: CodecError) -> Self {
+    match value {
+      CodecError::InsufficientDataLength(ident, size) => Self::InsufficientDataLength(ident, size),
+      CodecError::InvalidField(field, reason) => Self::InvalidField(field, reason),
+      CodecError::ChecksumMismatch(ident) => Self::InvalidField(ident, "checksum mismatch".into()),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+  InsufficientDataLength(String, usize),
+  InvalidField(String, String),
+  ChecksumMismatch(String),
+}
+
+impl Display for CodecError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CodecError::InsufficientDataLength(ident, size) => {
+        write!(f, "Insufficient data length while parsing {ident}: {size}")
+      }
+      CodecError::InvalidField(field, reason) => {
+        write!(f, "Invalid value of {field}: {reason}")
+      }
+      CodecError::ChecksumMismatch(ident) => {
+        write!(f, "Checksum mismatch while parsing {ident}")
+      }
+    }
+  }
+}
+
+impl Error for CodecError {}
@@ -3,13 +3,21 @@ use chrono::Utc;
 use super::error::TrackFileError;
 
 const HEADER_MAGIC_NUMBER: u64 = 0xfb9cfc9b116a158e;
-const HEADER_VERSION: u64 = 1;
+/// Fixed-size raw `TrackPoint` records
+pub const VERSION_FLAT: u16 = 1;
+/// Delta-encoded blocks of points carrying the optional extended fields,
+/// see `block.rs`
+pub const VERSION_BLOCKS: u16 = 2;
+const HEADER_VERSION: u16 = VERSION_BLOCKS;
+
+/// The header and every block carry a CRC32 checksum
+pub const FLAG_CHECKSUMS: u16 = 1;
 
 #[derive(Debug, Clone)]
-#[repr(C)]
 pub struct Header {
   pub magic: u64,
-  pub version: u64,
+  pub version: u16,
+  pub flags: u16,
   pub updated_at: u64,
   pub count: u64,
 }
@@ -19,6 +27,7 @@ impl Header {
     Ok(Self {
       magic: HEADER_MAGIC_NUMBER,
       version: HEADER_VERSION,
+      flags: FLAG_CHECKSUMS,
       updated_at: Utc::now().timestamp_millis() as u64,
       count: 0,
     })
@@ -28,10 +37,14 @@ impl Header {
     self.magic == HEADER_MAGIC_NUMBER
   }
 
-  pub fn version(&self) -> u64 {
+  pub fn version(&self) -> u16 {
     self.version
   }
 
+  pub fn has_checksums(&self) -> bool {
+    self.flags & FLAG_CHECKSUMS != 0
+  }
+
   pub fn timestamp(&self) -> u64 {
     self.updated_at
   }
@@ -44,8 +57,8 @@ impl Header {
     self.updated_at = Utc::now().timestamp_millis() as u64;
   }
 
-  pub fn inc(&mut self) {
-    self.count += 1;
+  pub fn set_count(&mut self, count: u64) {
+    self.count = count;
     self.touch();
   }
 }
@@ -0,0 +1,310 @@
+use std::{collections::BTreeMap, ops::Bound, path::Path};
+
+use serde::Serialize;
+
+use super::{
+  codec::Reader,
+  error::CodecError,
+  recordlog::{LogRecord, RecordLog},
+};
+
+const OP_UPSERT: u8 = 1;
+const OP_REMOVE: u8 = 2;
+// the log is compacted once it holds this many records per indexed track
+const COMPACTION_RATIO: usize = 4;
+const COMPACTION_MIN_RECORDS: usize = 4096;
+
+/// Header summary of a track kept in the index
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackSummary {
+  pub track_id: String,
+  pub count: u64,
+  pub first_ts: Option<i64>,
+  pub last_ts: Option<i64>,
+  pub updated_at: u64,
+}
+
+enum Record {
+  Upsert(TrackSummary),
+  Remove(String),
+}
+
+/// Index of all stored tracks sorted by track id.
+///
+/// It lives in memory and is persisted as a `RecordLog` of upserts and
+/// removals, which is rewritten from memory when it grows well beyond the
+/// number of tracks.
+pub struct TrackIndex {
+  log: RecordLog<Record>,
+  entries: BTreeMap<String, TrackSummary>,
+}
+
+impl LogRecord for Record {
+  const NAME: &'static str = "track index";
+
+  fn encode_body(&self, buf: &mut Vec<u8>) {
+    match self {
+      Record::Upsert(summary) => {
+        buf.push(OP_UPSERT);
+        buf.extend_from_slice(&(summary.track_id.len() as u32).to_le_bytes());
+        buf.extend_from_slice(summary.track_id.as_bytes());
+        buf.extend_from_slice(&summary.count.to_le_bytes());
+        // an empty track has no ts range, its count tells it apart
+        buf.extend_from_slice(&summary.first_ts.unwrap_or(0).to_le_bytes());
+        buf.extend_from_slice(&summary.last_ts.unwrap_or(0).to_le_bytes());
+        buf.extend_from_slice(&summary.updated_at.to_le_bytes());
+      }
+      Record::Remove(track_id) => {
+        buf.push(OP_REMOVE);
+        buf.extend_from_slice(&(track_id.len() as u32).to_le_bytes());
+        buf.extend_from_slice(track_id.as_bytes());
+      }
+    }
+  }
+
+  fn decode_body(reader: &mut Reader) -> Result<Self, CodecError> {
+    let op = reader.u8()?;
+    let len = reader.u32()? as usize;
+    let track_id = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+    match op {
+      OP_UPSERT => {
+        let count = reader.u64()?;
+        let first_ts = reader.i64()?;
+        let last_ts = reader.i64()?;
+        let has_points = count > 0;
+        Ok(Record::Upsert(TrackSummary {
+          track_id,
+          count,
+          first_ts: has_points.then_some(first_ts),
+          last_ts: has_points.then_some(last_ts),
+          updated_at: reader.u64()?,
+        }))
+      }
+      OP_REMOVE => Ok(Record::Remove(track_id)),
+      op => Err(CodecError::InvalidField(
+        "index.op".into(),
+        format!("unknown operation {op}"),
+      )),
+    }
+  }
+}
+
+fn snapshot(entries: &BTreeMap<String, TrackSummary>) -> Vec<Record> {
+  entries.values().cloned().map(Record::Upsert).collect()
+}
+
+impl TrackIndex {
+  /// Loads the index, returns `None` if it doesn't exist yet
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Option<Self>, std::io::Error> {
+    let mut entries = BTreeMap::new();
+    let log = RecordLog::open(path, |record| match record {
+      Record::Upsert(summary) => {
+        entries.insert(summary.track_id.clone(), summary);
+      }
+      Record::Remove(track_id) => {
+        entries.remove(&track_id);
+      }
+    })?;
+    Ok(log.map(|log| Self { log, entries }))
+  }
+
+  /// Creates the index from scratch with the given tracks
+  pub fn create<P: AsRef<Path>>(
+    path: P,
+    summaries: Vec<TrackSummary>,
+  ) -> Result<Self, std::io::Error> {
+    let entries = summaries
+      .into_iter()
+      .map(|summary| (summary.track_id.clone(), summary))
+      .collect();
+    let log = RecordLog::create(path, &snapshot(&entries))?;
+    Ok(Self { log, entries })
+  }
+
+  fn append(&mut self, records: &[Record]) -> Result<(), std::io::Error> {
+    self.log.append(records)?;
+    if self.log.records() > COMPACTION_MIN_RECORDS.max(self.entries.len() * COMPACTION_RATIO) {
+      self.log.compact(&snapshot(&self.entries))?;
+    }
+    Ok(())
+  }
+
+  /// Stores summaries of updated tracks
+  pub fn update(&mut self, summaries: Vec<TrackSummary>) -> Result<(), std::io::Error> {
+    if summaries.is_empty() {
+      return Ok(());
+    }
+    for summary in summaries.iter() {
+      self
+        .entries
+        .insert(summary.track_id.clone(), summary.clone());
+    }
+    let records: Vec<Record> = summaries.into_iter().map(Record::Upsert).collect();
+    self.append(&records)
+  }
+
+  pub fn remove(&mut self, track_id: &str) -> Result<(), std::io::Error> {
+    if self.entries.remove(track_id).is_some() {
+      self.append(&[Record::Remove(track_id.into())])?;
+    }
+    Ok(())
+  }
+
+  /// Replaces the whole index with the given tracks
+  pub fn rebuild(&mut self, summaries: Vec<TrackSummary>) -> Result<(), std::io::Error> {
+    self.entries = summaries
+      .into_iter()
+      .map(|summary| (summary.track_id.clone(), summary))
+      .collect();
+    self.log.compact(&snapshot(&self.entries))
+  }
+
+  pub fn sync(&self) -> Result<(), std::io::Error> {
+    self.log.sync()
+  }
+
+  pub fn get(&self, track_id: &str) -> Option<&TrackSummary> {
+    self.entries.get(track_id)
+  }
+
+  /// Returns the ids of the tracks which have points both at or before and
+  /// at or after `ts`
+  pub fn active_at(&self, ts: i64) -> Vec<String> {
+    self
+      .entries
+      .values()
+      .filter(|summary| summary.first_ts.is_some_and(|first| first <= ts))
+      .filter(|summary| summary.last_ts.is_some_and(|last| last >= ts))
+      .map(|summary| summary.track_id.clone())
+      .collect()
+  }
+
+  /// Returns the ids of the tracks with their update times, least recently
+  /// updated first
+  pub fn by_update(&self) -> Vec<(String, u64)> {
+    let mut tracks: Vec<(String, u64)> = self
+      .entries
+      .values()
+      .map(|summary| (summary.track_id.clone(), summary.updated_at))
+      .collect();
+    tracks.sort_by_key(|(_, updated_at)| *updated_at);
+    tracks
+  }
+
+  /// Returns up to `limit` tracks sorted by id, starting after `cursor`.
+  /// The second value is the cursor of the next page if there is one.
+  pub fn list(
+    &self,
+    prefix: Option<&str>,
+    updated_since: Option<u64>,
+    limit: usize,
+    cursor: Option<&str>,
+  ) -> (Vec<TrackSummary>, Option<String>) {
+    let prefix = prefix.unwrap_or("");
+    let start = match cursor {
+      Some(cursor) if cursor >= prefix => Bound::Excluded(cursor.to_owned()),
+      _ => Bound::Included(prefix.to_owned()),
+    };
+
+    let mut matching = self
+      .entries
+      .range((start, Bound::Unbounded))
+      .map(|(_, summary)| summary)
+      .take_while(|summary| summary.track_id.starts_with(prefix))
+      .filter(|summary| updated_since.is_none_or(|since| summary.updated_at >= since));
+
+    let page: Vec<TrackSummary> = matching.by_ref().take(limit).cloned().collect();
+    let next = match matching.next() {
+      Some(_) => page.last().map(|summary| summary.track_id.clone()),
+      None => None,
+    };
+    (page, next)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tracked-{}-{name}.index", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  fn summary(track_id: &str, updated_at: u64) -> TrackSummary {
+    TrackSummary {
+      track_id: track_id.into(),
+      count: 1,
+      first_ts: Some(0),
+      last_ts: Some(0),
+      updated_at,
+    }
+  }
+
+  fn ids(page: &[TrackSummary]) -> Vec<&str> {
+    page
+      .iter()
+      .map(|summary| summary.track_id.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn list_pages() {
+    let path = temp_path("list");
+    let summaries = ["ab1", "ab2", "ab3", "ac1", "b1"]
+      .iter()
+      .enumerate()
+      .map(|(i, track_id)| summary(track_id, i as u64))
+      .collect();
+    let index = TrackIndex::create(&path, summaries).unwrap();
+
+    let (page, next) = index.list(None, None, 2, None);
+    assert_eq!(ids(&page), ["ab1", "ab2"]);
+    assert_eq!(next.as_deref(), Some("ab2"));
+    let (page, next) = index.list(None, None, 2, next.as_deref());
+    assert_eq!(ids(&page), ["ab3", "ac1"]);
+    let (page, next) = index.list(None, None, 2, next.as_deref());
+    assert_eq!(ids(&page), ["b1"]);
+    assert_eq!(next, None);
+
+    // an exactly filled last page has no next one
+    let (page, next) = index.list(Some("ab"), None, 3, None);
+    assert_eq!(ids(&page), ["ab1", "ab2", "ab3"]);
+    assert_eq!(next, None);
+    let (page, _) = index.list(Some("ab"), None, 10, Some("ab1"));
+    assert_eq!(ids(&page), ["ab2", "ab3"]);
+    // a cursor before the prefix starts at the prefix
+    let (page, _) = index.list(Some("ac"), None, 10, Some("a"));
+    assert_eq!(ids(&page), ["ac1"]);
+    let (page, next) = index.list(None, Some(2), 1, None);
+    assert_eq!(ids(&page), ["ab3"]);
+    let (page, _) = index.list(None, Some(2), 10, next.as_deref());
+    assert_eq!(ids(&page), ["ac1", "b1"]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn changes_survive_reopening() {
+    let path = temp_path("reopen");
+    assert!(TrackIndex::open(&path).unwrap().is_none());
+    let mut index = TrackIndex::create(&path, vec![summary("a", 1), summary("b", 2)]).unwrap();
+    index
+      .update(vec![summary("a", 3), summary("c", 4)])
+      .unwrap();
+    index.remove("b").unwrap();
+    index.sync().unwrap();
+    drop(index);
+
+    let index = TrackIndex::open(&path).unwrap().unwrap();
+    let (page, _) = index.list(None, None, 10, None);
+    assert_eq!(ids(&page), ["a", "c"]);
+    assert_eq!(index.get("a").unwrap().updated_at, 3);
+    assert_eq!(
+      index.by_update(),
+      [("a".to_owned(), 3), ("c".to_owned(), 4)]
+    );
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -25,9 +25,14 @@ pub fn interpolate_track(points: &[TrackPoint]) -> Vec<TrackPoint> {
     let first_ts = points.first().unwrap().ts / step_ms;
     let last_ts = points.last().unwrap().ts / step_ms;
 
+    // extended fields are carried over from the last preceding point
+    let mut src = 0;
     let mut interpolated: Vec<TrackPoint> = (first_ts..last_ts)
       .filter_map(|ts| {
         let ts = ts * step_ms;
+        while src + 1 < points.len() && points[src + 1].ts <= ts {
+          src += 1;
+        }
         let lat = lat_spline.sample(ts as f64);
         let lng = lng_spline.sample(ts as f64);
         let hdg = hdg_spline.sample(ts as f64);
@@ -44,6 +49,10 @@ pub fn interpolate_track(points: &[TrackPoint]) -> Vec<TrackPoint> {
             hdg: hdg.unwrap() as i32,
             gs: gs.unwrap() as i32,
             alt: alt.unwrap() as i32,
+            vs: points[src].vs,
+            squawk: points[src].squawk,
+            on_ground: points[src].on_ground,
+            qnh: points[src].qnh,
           })
         }
       })
@@ -52,3 +61,113 @@ pub fn interpolate_track(points: &[TrackPoint]) -> Vec<TrackPoint> {
     interpolated
   }
 }
+
+fn lerp(a: f64, b: f64, k: f64) -> f64 {
+  a + (b - a) * k
+}
+
+// headings are interpolated along the shorter arc
+fn lerp_heading(a: i32, b: i32, k: f64) -> i32 {
+  let delta = (b - a + 540).rem_euclid(360) - 180;
+  (a as f64 + delta as f64 * k).round().rem_euclid(360.0) as i32
+}
+
+/// Computes the position at `ts` from the surrounding points, which should
+/// include a couple of points on both sides for the spline to be defined.
+/// Near the ends of a track the points are interpolated linearly, headings
+/// always are so that they turn along the shorter arc. Returns `None` if
+/// `ts` is outside of the points or if the points around it are more than
+/// `max_gap` ms apart.
+pub fn interpolate_at(points: &[TrackPoint], ts: i64, max_gap: Option<i64>) -> Option<TrackPoint> {
+  let next = points.partition_point(|p| p.ts <= ts);
+  let prev = &points[next.checked_sub(1)?];
+  if prev.ts == ts {
+    return Some(prev.clone());
+  }
+  let next = points.get(next)?;
+  if max_gap.is_some_and(|max_gap| next.ts - prev.ts > max_gap) {
+    return None;
+  }
+
+  let k = (ts - prev.ts) as f64 / (next.ts - prev.ts) as f64;
+  let mut point = TrackPoint { ts, ..prev.clone() };
+  point.hdg = lerp_heading(prev.hdg, next.hdg, k);
+  if points.len() >= 4 {
+    let sample =
+      |extract: Box<dyn Fn(&TrackPoint) -> f64>| create_spline(points, extract).sample(ts as f64);
+    let lat = sample(Box::new(|p| p.lat));
+    let lng = sample(Box::new(|p| p.lng));
+    let gs = sample(Box::new(|p| p.gs as f64));
+    let alt = sample(Box::new(|p| p.alt as f64));
+    if let (Some(lat), Some(lng), Some(gs), Some(alt)) = (lat, lng, gs, alt) {
+      point.lat = lat;
+      point.lng = lng;
+      point.gs = gs as i32;
+      point.alt = alt as i32;
+      return Some(point);
+    }
+  }
+
+  point.lat = lerp(prev.lat, next.lat, k);
+  point.lng = lerp(prev.lng, next.lng, k);
+  point.gs = lerp(prev.gs as f64, next.gs as f64, k).round() as i32;
+  point.alt = lerp(prev.alt as f64, next.alt as f64, k).round() as i32;
+  Some(point)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(ts: i64, lat: f64, hdg: i32) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat,
+      lng: 10.0,
+      hdg,
+      gs: 200,
+      alt: 1000,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn headings_take_the_shorter_arc() {
+    assert_eq!(lerp_heading(350, 10, 0.5), 0);
+    assert_eq!(lerp_heading(10, 350, 0.5), 0);
+    assert_eq!(lerp_heading(350, 10, 0.25), 355);
+    assert_eq!(lerp_heading(90, 270, 0.5), 0);
+    assert_eq!(lerp_heading(0, 90, 0.5), 45);
+    assert_eq!(lerp_heading(180, 180, 0.3), 180);
+  }
+
+  #[test]
+  fn interpolate_between_two_points() {
+    let points = [point(0, 10.0, 350), point(10_000, 11.0, 10)];
+    let at = interpolate_at(&points, 5000, None).unwrap();
+    assert_eq!(at.ts, 5000);
+    assert!((at.lat - 10.5).abs() < 1e-9);
+    assert_eq!(at.hdg, 0);
+
+    // exact hits return the stored point, outside of the track there is none
+    assert_eq!(interpolate_at(&points, 0, None).unwrap().lat, 10.0);
+    assert_eq!(interpolate_at(&points, 10_000, None).unwrap().lat, 11.0);
+    assert!(interpolate_at(&points, -1, None).is_none());
+    assert!(interpolate_at(&points, 10_001, None).is_none());
+    assert!(interpolate_at(&[], 0, None).is_none());
+  }
+
+  #[test]
+  fn interpolate_with_splines_and_gaps() {
+    let points: Vec<TrackPoint> = (0..6).map(|i| point(i * 1000, i as f64, 90)).collect();
+    let at = interpolate_at(&points, 2500, None).unwrap();
+    assert!((at.lat - 2.5).abs() < 1e-6);
+    assert_eq!(at.hdg, 90);
+
+    let points = [point(0, 0.0, 0), point(60_000, 1.0, 0)];
+    assert!(interpolate_at(&points, 30_000, Some(60_000)).is_some());
+    assert!(interpolate_at(&points, 30_000, Some(59_999)).is_none());
+    // points themselves are returned regardless of the gaps around them
+    assert!(interpolate_at(&points, 0, Some(1)).is_some());
+  }
+}
@@ -0,0 +1,285 @@
+use std::{
+  fs::{File, OpenOptions},
+  os::unix::fs::FileExt,
+  path::Path,
+};
+
+use log::warn;
+
+use super::{
+  codec::{decode_extended, encode_extended, Codec, Reader},
+  entry::TrackPoint,
+  error::CodecError,
+  metafile::MetaBlock,
+};
+
+const JOURNAL_MAGIC_NUMBER: u64 = 0x4a9e0c3d7b21f56c;
+
+/// A batch of points persisted before it is applied to the track files.
+///
+/// Along with the points it keeps the metablock and the point count and
+/// size of every affected track as they were before the batch so that
+/// counters can be recomputed exactly after a replay.
+#[derive(Debug)]
+pub struct JournalBatch {
+  pub meta: MetaBlock,
+  pub tracks: Vec<JournalTrack>,
+  /// points with indices of their tracks in `tracks`
+  pub points: Vec<(usize, TrackPoint)>,
+}
+
+#[derive(Debug)]
+pub struct JournalTrack {
+  pub track_id: String,
+  /// point count before the batch, `None` if the track did not exist
+  pub count: Option<u64>,
+  /// file size before the batch, zero if the track did not exist
+  pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct Journal {
+  file: File,
+}
+
+impl JournalBatch {
+  fn encode(&self) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.extend_from_slice(&JOURNAL_MAGIC_NUMBER.to_le_bytes());
+    self.meta.encode_into(&mut buf);
+
+    buf.extend_from_slice(&(self.tracks.len() as u32).to_le_bytes());
+    for track in self.tracks.iter() {
+      buf.extend_from_slice(&(track.track_id.len() as u32).to_le_bytes());
+      buf.extend_from_slice(track.track_id.as_bytes());
+      buf.extend_from_slice(&track.count.unwrap_or(u64::MAX).to_le_bytes());
+      buf.extend_from_slice(&track.size.to_le_bytes());
+    }
+
+    buf.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+    for (idx, point) in self.points.iter() {
+      buf.extend_from_slice(&(*idx as u32).to_le_bytes());
+      point.encode_into(&mut buf);
+      encode_extended(point, &mut buf);
+    }
+
+    let crc = crc32fast::hash(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+  }
+
+  fn decode(data: &[u8]) -> Result<Self, CodecError> {
+    if data.len() < 4 {
+      return Err(CodecError::InsufficientDataLength(
+        "journal".into(),
+        data.len(),
+      ));
+    }
+    let (body, crc) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body).to_le_bytes() != crc {
+      return Err(CodecError::ChecksumMismatch("journal".into()));
+    }
+
+    let mut reader = Reader::new(body, "journal");
+    if reader.u64()? != JOURNAL_MAGIC_NUMBER {
+      return Err(CodecError::InvalidField(
+        "journal.magic".into(),
+        "invalid magic number".into(),
+      ));
+    }
+    let meta = MetaBlock::decode_from(&mut reader)?;
+
+    let mut tracks = vec![];
+    for _ in 0..reader.u32()? {
+      let len = reader.u32()? as usize;
+      let track_id = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+      let count = match reader.u64()? {
+        u64::MAX => None,
+        count => Some(count),
+      };
+      tracks.push(JournalTrack {
+        track_id,
+        count,
+        size: reader.u64()?,
+      });
+    }
+
+    let mut points = vec![];
+    for _ in 0..reader.u32()? {
+      let idx = reader.u32()? as usize;
+      if idx >= tracks.len() {
+        return Err(CodecError::InvalidField(
+          "journal.track_index".into(),
+          format!("{idx} is out of range"),
+        ));
+      }
+      let mut point = TrackPoint::decode_from(&mut reader)?;
+      decode_extended(&mut reader, &mut point)?;
+      points.push((idx, point));
+    }
+
+    Ok(Self {
+      meta,
+      tracks,
+      points,
+    })
+  }
+}
+
+impl Journal {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+    let file = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .read(true)
+      .open(path)?;
+    Ok(Self { file })
+  }
+
+  /// Persists the batch, the call returns only when the data is on disk
+  pub fn write(&mut self, batch: &JournalBatch) -> Result<(), std::io::Error> {
+    let data = batch.encode();
+    self.file.set_len(0)?;
+    self.file.write_all_at(&data, 0)?;
+    self.file.sync_data()
+  }
+
+  /// Reads a batch left by an interrupted run. A torn or corrupted journal
+  /// means the batch was never started to be applied and is ignored.
+  pub fn read(&self) -> Result<Option<JournalBatch>, std::io::Error> {
+    let len = self.file.metadata()?.len() as usize;
+    if len == 0 {
+      return Ok(None);
+    }
+    let mut data = vec![0; len];
+    self.file.read_exact_at(&mut data, 0)?;
+    match JournalBatch::decode(&data) {
+      Ok(batch) => Ok(Some(batch)),
+      Err(err) => {
+        warn!("discarding incomplete journal: {err}");
+        Ok(None)
+      }
+    }
+  }
+
+  pub fn clear(&mut self) -> Result<(), std::io::Error> {
+    self.file.set_len(0)?;
+    self.file.sync_data()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn batch() -> JournalBatch {
+    JournalBatch {
+      meta: MetaBlock {
+        track_count: 2,
+        point_count: 10,
+        total_bytes: 1000,
+        ..Default::default()
+      },
+      tracks: vec![
+        JournalTrack {
+          track_id: "abc123".into(),
+          count: Some(10),
+          size: 1000,
+        },
+        JournalTrack {
+          track_id: "new".into(),
+          count: None,
+          size: 0,
+        },
+      ],
+      points: vec![
+        (
+          0,
+          TrackPoint {
+            ts: 1000,
+            lat: 10.0,
+            lng: 20.0,
+            squawk: Some(7700),
+            ..Default::default()
+          },
+        ),
+        (
+          1,
+          TrackPoint {
+            ts: 2000,
+            // stored as sent, points are validated before they are journaled
+            lat: -95.0,
+            vs: Some(-1500),
+            ..Default::default()
+          },
+        ),
+      ],
+    }
+  }
+
+  #[test]
+  fn batch_round_trip() {
+    let batch = batch();
+    let decoded = JournalBatch::decode(&batch.encode()).unwrap();
+    assert_eq!(decoded.meta.track_count, 2);
+    assert_eq!(decoded.meta.point_count, 10);
+    assert_eq!(decoded.meta.total_bytes, 1000);
+    assert_eq!(decoded.tracks.len(), 2);
+    assert_eq!(decoded.tracks[0].track_id, "abc123");
+    assert_eq!(decoded.tracks[0].count, Some(10));
+    assert_eq!(decoded.tracks[0].size, 1000);
+    assert_eq!(decoded.tracks[1].track_id, "new");
+    assert_eq!(decoded.tracks[1].count, None);
+    assert_eq!(decoded.tracks[1].size, 0);
+    assert_eq!(decoded.points.len(), 2);
+    for ((idx, point), (expected_idx, expected)) in decoded.points.iter().zip(batch.points.iter()) {
+      assert_eq!(idx, expected_idx);
+      assert_eq!(point.ts, expected.ts);
+      assert_eq!(point, expected);
+    }
+  }
+
+  #[test]
+  fn torn_or_corrupt_batch() {
+    let data = batch().encode();
+    for len in [0, 3, 8, data.len() - 1] {
+      assert!(JournalBatch::decode(&data[..len]).is_err(), "length {len}");
+    }
+    let mut corrupt = data.clone();
+    corrupt[20] ^= 1;
+    assert!(matches!(
+      JournalBatch::decode(&corrupt),
+      Err(CodecError::ChecksumMismatch(_))
+    ));
+  }
+
+  #[test]
+  fn track_index_out_of_range() {
+    let mut batch = batch();
+    batch.points[1].0 = 2;
+    assert!(matches!(
+      JournalBatch::decode(&batch.encode()),
+      Err(CodecError::InvalidField(field, _)) if field == "journal.track_index"
+    ));
+  }
+
+  #[test]
+  fn journal_file() {
+    let path = std::env::temp_dir().join(format!("tracked-journal-{}", std::process::id()));
+    let mut journal = Journal::open(&path).unwrap();
+    assert!(journal.read().unwrap().is_none());
+    journal.write(&batch()).unwrap();
+    let read = journal.read().unwrap().unwrap();
+    assert_eq!(read.points.len(), 2);
+
+    // a torn write is discarded
+    let len = journal.file.metadata().unwrap().len();
+    journal.file.set_len(len - 1).unwrap();
+    assert!(journal.read().unwrap().is_none());
+
+    journal.clear().unwrap();
+    assert!(journal.read().unwrap().is_none());
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -1,60 +1,46 @@
 use std::{
-  fmt::Display,
   fs::{File, OpenOptions},
   io::Write,
-  mem::size_of,
   os::unix::fs::FileExt,
-  path::Path,
-  ptr::slice_from_raw_parts,
+  path::{Path, PathBuf},
 };
 
 use chrono::Utc;
 
-use super::error::MetaFileError;
+use super::{codec::Codec, error::MetaFileError, replace::replace_file};
 
-#[derive(Debug, Clone)]
-#[repr(C)]
+/// Version 1 blocks consist of the counters and `updated_at` only
+pub const METABLOCK_VERSION: u64 = 2;
+
+#[derive(Debug, Clone, Default)]
 pub struct MetaBlock {
   pub track_count: u64,
   pub point_count: u64,
   pub updated_at: u64,
+  /// size of all track files in bytes
+  pub total_bytes: u64,
+  /// number of corrupt track files found by the last scan
+  pub corrupt_count: u64,
+  /// the least recent `updated_at` of a track as of the last scan,
+  /// zero if unknown
+  pub oldest_updated_at: u64,
+  /// the most recent `updated_at` of a track
+  pub newest_updated_at: u64,
 }
 
 #[derive(Debug)]
 pub struct MetaFile {
+  path: PathBuf,
   file: File,
 }
 
-#[allow(clippy::size_of_in_element_count)]
-fn to_raw<T: Sized>(obj: &T) -> Vec<u8> {
-  let slice = slice_from_raw_parts(obj, size_of::<T>()) as *const [u8];
-  let slice = unsafe { &*slice };
-  slice.into()
-}
-
-fn from_raw<T: Sized + Clone, I: AsRef<str> + Display>(
-  data: &[u8],
-  ident: I,
-) -> std::result::Result<T, MetaFileError> {
-  if data.len() < size_of::<T>() {
-    Err(MetaFileError::InsufficientDataLength(
-      ident.to_string(),
-      data.len(),
-    ))
-  } else {
-    let slice = data as *const [u8] as *const T;
-    let tp = unsafe { &*slice };
-    Ok(tp.clone())
-  }
-}
-
 impl MetaFile {
   pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, MetaFileError> {
     let path = path.as_ref().to_path_buf();
     let res = OpenOptions::new().write(true).read(true).open(&path);
     match res {
       Ok(file) => {
-        let mf = Self { file };
+        let mf = Self { path, file };
         Ok(mf)
       }
       Err(err) => match err.kind() {
@@ -68,36 +54,39 @@ impl MetaFile {
   }
 
   pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, MetaFileError> {
+    let path = path.as_ref().to_path_buf();
     let mut file = OpenOptions::new()
       .create(true)
+      .truncate(true)
       .write(true)
       .read(true)
       .open(&path)?;
     let block = MetaBlock {
-      track_count: 0,
-      point_count: 0,
       updated_at: Utc::now().timestamp_millis() as u64,
+      ..Default::default()
     };
-    let raw_block = to_raw(&block);
+    let raw_block = block.encode();
     file.write_all(&raw_block)?;
-    Ok(Self { file })
+    Ok(Self { path, file })
   }
 
   fn make_buf(&self) -> Vec<u8> {
-    let size = size_of::<MetaBlock>();
+    let size = MetaBlock::SIZE;
     let buf = vec![0; size];
     buf
   }
 
   pub fn read_block(&mut self) -> Result<MetaBlock, MetaFileError> {
+    // shorter version 1 blocks are padded with zeros
     let mut buf = self.make_buf();
     self.file.read_at(&mut buf, 0)?;
-    from_raw(&buf, "metablock")
+    Ok(MetaBlock::decode(&buf)?)
   }
 
+  /// Replaces the block with a new file so that a crash never leaves it
+  /// half written
   pub fn write_block(&mut self, block: &MetaBlock) -> Result<(), MetaFileError> {
-    let buf = to_raw(block);
-    self.file.write_at(&buf, 0)?;
+    self.file = replace_file(&self.path, &block.encode())?;
     Ok(())
   }
 }
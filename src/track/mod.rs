@@ -1,7 +1,18 @@
+pub mod asyncstore;
+pub mod block;
+pub mod cache;
+pub mod codec;
 pub mod entry;
 pub mod error;
 pub mod header;
+pub mod index;
 pub mod interpolate;
+pub mod journal;
 pub mod metafile;
+pub mod recordlog;
+pub mod replace;
+pub mod simplify;
+pub mod spatial;
 pub mod store;
 pub mod trackfile;
+pub mod trackmeta;
@@ -0,0 +1,243 @@
+use std::{
+  fs::{File, OpenOptions},
+  marker::PhantomData,
+  os::unix::fs::FileExt,
+  path::{Path, PathBuf},
+};
+
+use log::warn;
+
+use super::{codec::Reader, error::CodecError, replace::replace_file};
+
+/// A record of a `RecordLog`
+pub trait LogRecord: Sized {
+  /// what the log holds, used in errors and warnings
+  const NAME: &'static str;
+
+  /// Writes the record, its checksum is added by the log
+  fn encode_body(&self, buf: &mut Vec<u8>);
+
+  fn decode_body(reader: &mut Reader) -> Result<Self, CodecError>;
+}
+
+/// Append-only log of records, every record carrying its own CRC32.
+///
+/// The log only persists the records, the state they build is kept by the
+/// owner which replays them on open. A torn record at the end of the log
+/// is dropped on load. The owner rewrites the log from its state once it
+/// holds many more records than needed.
+pub struct RecordLog<R> {
+  path: PathBuf,
+  file: File,
+  len: u64,
+  records: usize,
+  _record: PhantomData<R>,
+}
+
+fn encode_record<R: LogRecord>(record: &R, buf: &mut Vec<u8>) {
+  let start = buf.len();
+  record.encode_body(buf);
+  let crc = crc32fast::hash(&buf[start..]);
+  buf.extend_from_slice(&crc.to_le_bytes());
+}
+
+fn decode_record<R: LogRecord>(reader: &mut Reader) -> Result<R, CodecError> {
+  let start = reader.pos();
+  let record = R::decode_body(reader)?;
+  let crc = crc32fast::hash(reader.consumed_since(start));
+  if reader.u32()? != crc {
+    return Err(CodecError::ChecksumMismatch(format!("{} record", R::NAME)));
+  }
+  Ok(record)
+}
+
+impl<R: LogRecord> RecordLog<R> {
+  /// Replays the records of the log through `apply`, returns `None` if the
+  /// log doesn't exist yet
+  pub fn open<P: AsRef<Path>, F: FnMut(R)>(
+    path: P,
+    mut apply: F,
+  ) -> Result<Option<Self>, std::io::Error> {
+    let path = path.as_ref().to_path_buf();
+    let file = match OpenOptions::new().read(true).write(true).open(&path) {
+      Ok(file) => file,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(err),
+    };
+
+    let data = std::fs::read(&path)?;
+    let mut reader = Reader::new(&data, R::NAME);
+    let mut records = 0;
+    let mut len = 0;
+    while reader.remaining() > 0 {
+      match decode_record(&mut reader) {
+        Ok(record) => apply(record),
+        Err(err) => {
+          warn!("dropping the tail of the {}: {err}", R::NAME);
+          break;
+        }
+      }
+      records += 1;
+      len = reader.pos() as u64;
+    }
+    file.set_len(len)?;
+
+    Ok(Some(Self {
+      path,
+      file,
+      len,
+      records,
+      _record: PhantomData,
+    }))
+  }
+
+  /// Creates the log holding `records`, replacing an existing one
+  pub fn create<P: AsRef<Path>>(path: P, records: &[R]) -> Result<Self, std::io::Error> {
+    let path = path.as_ref().to_path_buf();
+    let (file, len) = Self::write_snapshot(&path, records)?;
+    Ok(Self {
+      path,
+      file,
+      len,
+      records: records.len(),
+      _record: PhantomData,
+    })
+  }
+
+  // atomically replaces the log with the records
+  fn write_snapshot(path: &Path, records: &[R]) -> Result<(File, u64), std::io::Error> {
+    let mut buf = vec![];
+    for record in records.iter() {
+      encode_record(record, &mut buf);
+    }
+    let file = replace_file(path, &buf)?;
+    Ok((file, buf.len() as u64))
+  }
+
+  /// Replaces the log with `records`, which should rebuild the current
+  /// state of the owner
+  pub fn compact(&mut self, records: &[R]) -> Result<(), std::io::Error> {
+    (self.file, self.len) = Self::write_snapshot(&self.path, records)?;
+    self.records = records.len();
+    Ok(())
+  }
+
+  pub fn append(&mut self, records: &[R]) -> Result<(), std::io::Error> {
+    if records.is_empty() {
+      return Ok(());
+    }
+    let mut buf = vec![];
+    for record in records.iter() {
+      encode_record(record, &mut buf);
+    }
+    self.file.write_all_at(&buf, self.len)?;
+    self.len += buf.len() as u64;
+    self.records += records.len();
+    Ok(())
+  }
+
+  /// Number of records in the log, including the superseded ones
+  pub fn records(&self) -> usize {
+    self.records
+  }
+
+  pub fn sync(&self) -> Result<(), std::io::Error> {
+    self.file.sync_data()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct Value(u64);
+
+  impl LogRecord for Value {
+    const NAME: &'static str = "test log";
+
+    fn encode_body(&self, buf: &mut Vec<u8>) {
+      buf.extend_from_slice(&self.0.to_le_bytes());
+    }
+
+    fn decode_body(reader: &mut Reader) -> Result<Self, CodecError> {
+      Ok(Self(reader.u64()?))
+    }
+  }
+
+  fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tracked-{}-{name}.log", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  fn replay(path: &Path) -> (RecordLog<Value>, Vec<u64>) {
+    let mut values = vec![];
+    let log = RecordLog::open(path, |value: Value| values.push(value.0))
+      .unwrap()
+      .unwrap();
+    (log, values)
+  }
+
+  #[test]
+  fn append_and_replay() {
+    let path = temp_path("replay");
+    assert!(RecordLog::<Value>::open(&path, |_| ()).unwrap().is_none());
+    let mut log = RecordLog::create(&path, &[Value(1)]).unwrap();
+    log.append(&[Value(2), Value(3)]).unwrap();
+    log.append(&[]).unwrap();
+    log.sync().unwrap();
+    assert_eq!(log.records(), 3);
+    drop(log);
+
+    let (log, values) = replay(&path);
+    assert_eq!(values, [1, 2, 3]);
+    assert_eq!(log.records(), 3);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn torn_tail_is_dropped() {
+    let path = temp_path("torn");
+    let mut log = RecordLog::create(&path, &[Value(1), Value(2)]).unwrap();
+    log.append(&[Value(3)]).unwrap();
+    drop(log);
+    let len = std::fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 2).unwrap();
+    drop(file);
+
+    // the log is cut after the last valid record and appends follow it
+    let (mut log, values) = replay(&path);
+    assert_eq!(values, [1, 2]);
+    log.append(&[Value(4)]).unwrap();
+    drop(log);
+    let (_, values) = replay(&path);
+    assert_eq!(values, [1, 2, 4]);
+
+    // a corrupt record drops everything after it
+    let mut data = std::fs::read(&path).unwrap();
+    data[12] ^= 1;
+    std::fs::write(&path, &data).unwrap();
+    let (log, values) = replay(&path);
+    assert_eq!(values, [1]);
+    assert_eq!(log.records(), 1);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn compact_replaces_the_records() {
+    let path = temp_path("compact");
+    let mut log = RecordLog::create(&path, &[Value(1), Value(2)]).unwrap();
+    log.compact(&[Value(5)]).unwrap();
+    assert_eq!(log.records(), 1);
+    log.append(&[Value(6)]).unwrap();
+    drop(log);
+
+    let (_, values) = replay(&path);
+    assert_eq!(values, [5, 6]);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    assert!(!Path::new(&tmp_path).exists());
+    std::fs::remove_file(&path).unwrap();
+  }
+}
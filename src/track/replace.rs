@@ -0,0 +1,26 @@
+use std::{
+  fs::{rename, File, OpenOptions},
+  io::Write,
+  path::Path,
+};
+
+/// Atomically replaces the file at `path` with `data` and returns it opened
+/// for reading and writing.
+///
+/// The data goes to a temporary file next to it which is synced and renamed
+/// over the old one, then the directory is synced so that the rename
+/// survives a crash too.
+pub fn replace_file(path: &Path, data: &[u8]) -> Result<File, std::io::Error> {
+  let mut tmp_path = path.as_os_str().to_owned();
+  tmp_path.push(".tmp");
+  let mut file = File::create(&tmp_path)?;
+  file.write_all(data)?;
+  file.sync_all()?;
+  rename(&tmp_path, path)?;
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  File::open(dir)?.sync_all()?;
+  OpenOptions::new().read(true).write(true).open(path)
+}
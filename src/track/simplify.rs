@@ -0,0 +1,192 @@
+use super::entry::TrackPoint;
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Tolerances of `simplify`
+#[derive(Debug, Clone, Copy)]
+pub struct Simplification {
+  /// meters off the simplified track a point may be
+  pub distance: f64,
+  /// ft off the altitude of the simplified track a point may be
+  pub alt: i32,
+}
+
+// central angle between two points, radians
+fn angular_distance(a: &TrackPoint, b: &TrackPoint) -> f64 {
+  let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+  let dlat = lat2 - lat1;
+  let dlng = (b.lng - a.lng).to_radians();
+  let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+  2.0 * h.sqrt().min(1.0).asin()
+}
+
+// initial bearing from a to b, radians
+fn bearing(a: &TrackPoint, b: &TrackPoint) -> f64 {
+  let (lat1, lat2) = (a.lat.to_radians(), b.lat.to_radians());
+  let dlng = (b.lng - a.lng).to_radians();
+  let y = dlng.sin() * lat2.cos();
+  let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlng.cos();
+  y.atan2(x)
+}
+
+// distance in meters from p to the great circle arc between a and b
+fn distance_to_arc(p: &TrackPoint, a: &TrackPoint, b: &TrackPoint) -> f64 {
+  let d_ap = angular_distance(a, p);
+  let d_ab = angular_distance(a, b);
+  if d_ab == 0.0 {
+    return d_ap * EARTH_RADIUS_M;
+  }
+  let dtheta = bearing(a, p) - bearing(a, b);
+  if dtheta.cos() < 0.0 {
+    // p lies behind a
+    return d_ap * EARTH_RADIUS_M;
+  }
+  let xt = (d_ap.sin() * dtheta.sin()).asin();
+  let at = (d_ap.cos() / xt.cos()).clamp(-1.0, 1.0).acos();
+  if at > d_ab {
+    // p lies beyond b
+    return angular_distance(b, p) * EARTH_RADIUS_M;
+  }
+  xt.abs() * EARTH_RADIUS_M
+}
+
+// altitude difference from a linear climb or descent between a and b
+fn altitude_deviation(p: &TrackPoint, a: &TrackPoint, b: &TrackPoint) -> f64 {
+  let expected = if b.ts == a.ts {
+    a.alt as f64
+  } else {
+    let k = (p.ts - a.ts) as f64 / (b.ts - a.ts) as f64;
+    a.alt as f64 + (b.alt - a.alt) as f64 * k
+  };
+  (p.alt as f64 - expected).abs()
+}
+
+/// Ramer-Douglas-Peucker simplification of a track on the sphere. A point is
+/// dropped when it's within `distance` of the simplified track and its
+/// altitude is within `alt` of the altitude the track would have there
+/// climbing or descending steadily. The first and the last points are
+/// always kept.
+pub fn simplify(points: &[TrackPoint], tolerance: &Simplification) -> Vec<TrackPoint> {
+  if points.len() < 3 {
+    return points.to_vec();
+  }
+
+  // errors are normalized by the tolerances so that either of them exceeded
+  // keeps the point, a zero tolerance keeps every deviation
+  let error = |p: &TrackPoint, a: &TrackPoint, b: &TrackPoint| {
+    let distance = distance_to_arc(p, a, b) / tolerance.distance.max(f64::MIN_POSITIVE);
+    let alt = altitude_deviation(p, a, b) / (tolerance.alt.max(0) as f64).max(f64::MIN_POSITIVE);
+    distance.max(alt)
+  };
+
+  let mut keep = vec![false; points.len()];
+  keep[0] = true;
+  keep[points.len() - 1] = true;
+  let mut ranges = vec![(0, points.len() - 1)];
+  while let Some((start, end)) = ranges.pop() {
+    let (a, b) = (&points[start], &points[end]);
+    let farthest = (start + 1..end)
+      .map(|idx| (idx, error(&points[idx], a, b)))
+      .max_by(|(_, x), (_, y)| x.total_cmp(y));
+    if let Some((idx, err)) = farthest {
+      if err > 1.0 {
+        keep[idx] = true;
+        ranges.push((start, idx));
+        ranges.push((idx, end));
+      }
+    }
+  }
+
+  points
+    .iter()
+    .zip(keep)
+    .filter(|(_, keep)| *keep)
+    .map(|(point, _)| point.clone())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn point(ts: i64, lat: f64, lng: f64, alt: i32) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat,
+      lng,
+      alt,
+      ..Default::default()
+    }
+  }
+
+  fn timestamps(points: &[TrackPoint]) -> Vec<i64> {
+    points.iter().map(|p| p.ts).collect()
+  }
+
+  const TOLERANCE: Simplification = Simplification {
+    distance: 100.0,
+    alt: 100,
+  };
+
+  #[test]
+  fn straight_steady_track_keeps_its_ends() {
+    // along the equator, climbing steadily
+    let points: Vec<TrackPoint> = (0..10)
+      .map(|i| point(i * 1000, 0.0, i as f64 * 0.01, i as i32 * 500))
+      .collect();
+    assert_eq!(timestamps(&simplify(&points, &TOLERANCE)), [0, 9000]);
+    assert_eq!(simplify(&points[..2], &TOLERANCE).len(), 2);
+    assert!(simplify(&[], &TOLERANCE).is_empty());
+  }
+
+  #[test]
+  fn turns_and_level_offs_are_kept() {
+    // about 111 m north of the line at the second point
+    let points = [
+      point(0, 0.0, 0.0, 1000),
+      point(1000, 0.001, 0.01, 1000),
+      point(2000, 0.0, 0.02, 1000),
+    ];
+    assert_eq!(timestamps(&simplify(&points, &TOLERANCE)), [0, 1000, 2000]);
+    let loose = Simplification {
+      distance: 200.0,
+      ..TOLERANCE
+    };
+    assert_eq!(timestamps(&simplify(&points, &loose)), [0, 2000]);
+
+    // a climb leveling off deviates from the steady climb
+    let points = [
+      point(0, 0.0, 0.0, 0),
+      point(1000, 0.0, 0.01, 1000),
+      point(2000, 0.0, 0.02, 1000),
+    ];
+    assert_eq!(timestamps(&simplify(&points, &TOLERANCE)), [0, 1000, 2000]);
+  }
+
+  #[test]
+  fn zero_tolerance_keeps_every_deviation() {
+    let points = [
+      point(0, 0.0, 0.0, 0),
+      point(1000, 0.0, 0.01, 1),
+      point(2000, 0.0, 0.02, 0),
+    ];
+    let exact = Simplification {
+      distance: 0.0,
+      alt: 0,
+    };
+    assert_eq!(simplify(&points, &exact).len(), 3);
+  }
+
+  #[test]
+  fn distance_to_arc_ends() {
+    let a = point(0, 0.0, 0.0, 0);
+    let b = point(0, 0.0, 1.0, 0);
+    // a degree of latitude or longitude at the equator is about 111 km
+    let beside = distance_to_arc(&point(0, 0.5, 0.5, 0), &a, &b);
+    assert!((beside - 55_597.0).abs() < 10.0, "{beside}");
+    let behind = distance_to_arc(&point(0, 0.0, -1.0, 0), &a, &b);
+    assert!((behind - 111_195.0).abs() < 10.0, "{behind}");
+    let beyond = distance_to_arc(&point(0, 0.0, 2.0, 0), &a, &b);
+    assert!((beyond - 111_195.0).abs() < 10.0, "{beyond}");
+  }
+}
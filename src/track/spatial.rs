@@ -0,0 +1,397 @@
+use std::{
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  path::Path,
+  sync::Arc,
+};
+
+use serde::Serialize;
+
+use super::{
+  codec::Reader,
+  entry::TrackPoint,
+  error::CodecError,
+  recordlog::{LogRecord, RecordLog},
+};
+
+/// Size of a grid cell in degrees of latitude and longitude
+pub const CELL_DEGREES: f64 = 0.5;
+/// Length of a time slice in ms
+pub const SLICE_MS: i64 = 3_600_000;
+
+const OP_ADD: u8 = 1;
+const OP_REMOVE: u8 = 2;
+// the log is compacted once it holds this many records per live cell entry
+const COMPACTION_RATIO: usize = 2;
+const COMPACTION_MIN_RECORDS: usize = 16384;
+
+/// A time slice of a grid cell, ordered by slice first so that a time
+/// window maps to a contiguous range of keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct CellKey {
+  slice: i64,
+  lat: i32,
+  lng: i32,
+}
+
+impl CellKey {
+  fn of(point: &TrackPoint) -> Self {
+    Self {
+      slice: point.ts.div_euclid(SLICE_MS),
+      lat: cell(point.lat),
+      lng: cell(point.lng),
+    }
+  }
+}
+
+fn cell(deg: f64) -> i32 {
+  (deg / CELL_DEGREES).floor() as i32
+}
+
+/// Area to search, boxes crossing the antimeridian are not supported
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+  pub min_lat: f64,
+  pub min_lng: f64,
+  pub max_lat: f64,
+  pub max_lng: f64,
+}
+
+impl BoundingBox {
+  pub fn is_valid(&self) -> bool {
+    (-90.0..=90.0).contains(&self.min_lat)
+      && (-90.0..=90.0).contains(&self.max_lat)
+      && (-180.0..=180.0).contains(&self.min_lng)
+      && (-180.0..=180.0).contains(&self.max_lng)
+      && self.min_lat <= self.max_lat
+      && self.min_lng <= self.max_lng
+  }
+
+  pub fn contains(&self, point: &TrackPoint) -> bool {
+    (self.min_lat..=self.max_lat).contains(&point.lat)
+      && (self.min_lng..=self.max_lng).contains(&point.lng)
+  }
+}
+
+/// A visit of a track to the searched area
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackCrossing {
+  pub track_id: String,
+  pub entered_at: i64,
+  pub left_at: i64,
+}
+
+enum Record {
+  Add(String, CellKey),
+  Remove(String),
+}
+
+/// Grid index of the cells visited by every track per time slice.
+///
+/// The index only tells which tracks may have been in an area at some
+/// time, the points themselves are checked by the caller. Cells are never
+/// removed from a track before the whole track is, so the index may return
+/// tracks which have been truncated since.
+///
+/// The whole index lives in memory with an entry per cell and hour visited
+/// by every track, it's only bounded by tracks being removed, explicitly or
+/// by the retention policy. It is persisted as a `RecordLog` the same way
+/// `TrackIndex` is.
+pub struct SpatialIndex {
+  log: RecordLog<Record>,
+  grid: Grid,
+}
+
+#[derive(Default)]
+struct Grid {
+  entries: usize,
+  cells: BTreeMap<CellKey, BTreeSet<Arc<str>>>,
+  tracks: HashMap<Arc<str>, HashSet<CellKey>>,
+}
+
+impl LogRecord for Record {
+  const NAME: &'static str = "spatial index";
+
+  fn encode_body(&self, buf: &mut Vec<u8>) {
+    let track_id = match self {
+      Record::Add(track_id, _) => {
+        buf.push(OP_ADD);
+        track_id
+      }
+      Record::Remove(track_id) => {
+        buf.push(OP_REMOVE);
+        track_id
+      }
+    };
+    buf.extend_from_slice(&(track_id.len() as u32).to_le_bytes());
+    buf.extend_from_slice(track_id.as_bytes());
+    if let Record::Add(_, key) = self {
+      buf.extend_from_slice(&key.slice.to_le_bytes());
+      buf.extend_from_slice(&key.lat.to_le_bytes());
+      buf.extend_from_slice(&key.lng.to_le_bytes());
+    }
+  }
+
+  fn decode_body(reader: &mut Reader) -> Result<Self, CodecError> {
+    let op = reader.u8()?;
+    let len = reader.u32()? as usize;
+    let track_id = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+    match op {
+      OP_ADD => Ok(Record::Add(
+        track_id,
+        CellKey {
+          slice: reader.i64()?,
+          lat: reader.i32()?,
+          lng: reader.i32()?,
+        },
+      )),
+      OP_REMOVE => Ok(Record::Remove(track_id)),
+      op => Err(CodecError::InvalidField(
+        "spatial.op".into(),
+        format!("unknown operation {op}"),
+      )),
+    }
+  }
+}
+
+impl Grid {
+  // returns true if the cell is new to the track
+  fn insert(&mut self, track_id: &str, key: CellKey) -> bool {
+    let track_id: Arc<str> = match self.tracks.get_key_value(track_id) {
+      Some((track_id, _)) => track_id.clone(),
+      None => track_id.into(),
+    };
+    if !self.tracks.entry(track_id.clone()).or_default().insert(key) {
+      return false;
+    }
+    self.cells.entry(key).or_default().insert(track_id);
+    self.entries += 1;
+    true
+  }
+
+  // returns true if the track was indexed
+  fn forget(&mut self, track_id: &str) -> bool {
+    let Some(keys) = self.tracks.remove(track_id) else {
+      return false;
+    };
+    for key in keys.iter() {
+      if let Some(ids) = self.cells.get_mut(key) {
+        ids.remove(track_id);
+        if ids.is_empty() {
+          self.cells.remove(key);
+        }
+      }
+    }
+    self.entries -= keys.len();
+    true
+  }
+
+  // one record per live cell entry
+  fn snapshot(&self) -> Vec<Record> {
+    let mut records = vec![];
+    for (track_id, keys) in self.tracks.iter() {
+      for key in keys.iter() {
+        records.push(Record::Add(track_id.to_string(), *key));
+      }
+    }
+    records
+  }
+}
+
+impl SpatialIndex {
+  /// Loads the index, returns `None` if it doesn't exist yet
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Option<Self>, std::io::Error> {
+    let mut grid = Grid::default();
+    let log = RecordLog::open(path, |record| match record {
+      Record::Add(track_id, key) => {
+        grid.insert(&track_id, key);
+      }
+      Record::Remove(track_id) => {
+        grid.forget(&track_id);
+      }
+    })?;
+    Ok(log.map(|log| Self { log, grid }))
+  }
+
+  /// Creates an empty index, tracks are added with `add` and persisted
+  /// with `sync`
+  pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+    Ok(Self {
+      log: RecordLog::create(path, &[])?,
+      grid: Grid::default(),
+    })
+  }
+
+  fn append(&mut self, records: &[Record]) -> Result<(), std::io::Error> {
+    self.log.append(records)?;
+    if self.log.records() > COMPACTION_MIN_RECORDS.max(self.grid.entries * COMPACTION_RATIO) {
+      self.log.compact(&self.grid.snapshot())?;
+    }
+    Ok(())
+  }
+
+  /// Adds the cells of the points to the track
+  pub fn add(&mut self, track_id: &str, points: &[TrackPoint]) -> Result<(), std::io::Error> {
+    let mut records = vec![];
+    for point in points.iter() {
+      let key = CellKey::of(point);
+      if self.grid.insert(track_id, key) {
+        records.push(Record::Add(track_id.into(), key));
+      }
+    }
+    self.append(&records)
+  }
+
+  pub fn remove(&mut self, track_id: &str) -> Result<(), std::io::Error> {
+    if self.grid.forget(track_id) {
+      self.append(&[Record::Remove(track_id.into())])?;
+    }
+    Ok(())
+  }
+
+  /// Drops all tracks for which `f` returns false
+  pub fn retain<F: Fn(&str) -> bool>(&mut self, f: F) -> Result<(), std::io::Error> {
+    let stale: Vec<Arc<str>> = self
+      .grid
+      .tracks
+      .keys()
+      .filter(|track_id| !f(track_id))
+      .cloned()
+      .collect();
+    if stale.is_empty() {
+      return Ok(());
+    }
+    for track_id in stale.iter() {
+      self.grid.forget(track_id);
+    }
+    self.log.compact(&self.grid.snapshot())
+  }
+
+  pub fn sync(&self) -> Result<(), std::io::Error> {
+    self.log.sync()
+  }
+
+  /// Returns the ids of the tracks which have points in the cells covering
+  /// the box between `after` and `before`, sorted
+  pub fn candidates(&self, bbox: &BoundingBox, after: i64, before: i64) -> Vec<String> {
+    let (min_lat, max_lat) = (cell(bbox.min_lat), cell(bbox.max_lat));
+    let (min_lng, max_lng) = (cell(bbox.min_lng), cell(bbox.max_lng));
+    let mut res = BTreeSet::new();
+    let (Some((first, _)), Some((last, _))) = (
+      self.grid.cells.first_key_value(),
+      self.grid.cells.last_key_value(),
+    ) else {
+      return vec![];
+    };
+    // wide windows are clamped to the slices actually indexed
+    let from = after.div_euclid(SLICE_MS).max(first.slice);
+    let to = before.div_euclid(SLICE_MS).min(last.slice);
+    for slice in from..=to {
+      let from = CellKey {
+        slice,
+        lat: min_lat,
+        lng: i32::MIN,
+      };
+      let to = CellKey {
+        slice,
+        lat: max_lat,
+        lng: i32::MAX,
+      };
+      for (_, ids) in self
+        .grid
+        .cells
+        .range(from..=to)
+        .filter(|(key, _)| (min_lng..=max_lng).contains(&key.lng))
+      {
+        res.extend(ids.iter().cloned());
+      }
+    }
+    res
+      .into_iter()
+      .map(|track_id| track_id.to_string())
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tracked-{}-{name}.spatial", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  fn point(ts: i64, lat: f64, lng: f64) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat,
+      lng,
+      ..Default::default()
+    }
+  }
+
+  fn bbox(min_lat: f64, min_lng: f64, max_lat: f64, max_lng: f64) -> BoundingBox {
+    BoundingBox {
+      min_lat,
+      min_lng,
+      max_lat,
+      max_lng,
+    }
+  }
+
+  #[test]
+  fn candidates_by_cell_and_slice() {
+    let path = temp_path("candidates");
+    let mut index = SpatialIndex::create(&path).unwrap();
+    let hour = SLICE_MS;
+    index
+      .add("a", &[point(0, 48.1, 11.6), point(hour, 50.0, 8.6)])
+      .unwrap();
+    index.add("b", &[point(hour, 48.2, 11.7)]).unwrap();
+    index.add("c", &[point(5 * hour, -33.9, 151.2)]).unwrap();
+
+    let munich = bbox(48.0, 11.0, 48.4, 12.0);
+    assert_eq!(index.candidates(&munich, 0, 2 * hour), ["a", "b"]);
+    assert_eq!(index.candidates(&munich, hour, 2 * hour), ["b"]);
+    assert!(index.candidates(&munich, 2 * hour, 3 * hour).is_empty());
+    // wide windows only cover the indexed slices
+    assert_eq!(
+      index.candidates(&bbox(-90.0, -180.0, 90.0, 180.0), i64::MIN, i64::MAX),
+      ["a", "b", "c"]
+    );
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn removals_survive_reopening() {
+    let path = temp_path("reopen");
+    assert!(SpatialIndex::open(&path).unwrap().is_none());
+    let mut index = SpatialIndex::create(&path).unwrap();
+    for track_id in ["a", "b", "c"] {
+      index.add(track_id, &[point(0, 10.0, 10.0)]).unwrap();
+    }
+    index.remove("a").unwrap();
+    index.retain(|track_id| track_id != "b").unwrap();
+    index.add("d", &[point(0, 10.0, 10.0)]).unwrap();
+    index.sync().unwrap();
+    drop(index);
+
+    let index = SpatialIndex::open(&path).unwrap().unwrap();
+    let all = bbox(-90.0, -180.0, 90.0, 180.0);
+    assert_eq!(index.candidates(&all, 0, 0), ["c", "d"]);
+    assert_eq!(index.grid.entries, 2);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn bounding_box() {
+    assert!(bbox(48.0, 11.0, 48.4, 12.0).is_valid());
+    assert!(!bbox(48.4, 11.0, 48.0, 12.0).is_valid());
+    assert!(!bbox(0.0, 170.0, 10.0, 190.0).is_valid());
+    let b = bbox(48.0, 11.0, 48.4, 12.0);
+    assert!(b.contains(&point(0, 48.0, 12.0)));
+    assert!(!b.contains(&point(0, 48.5, 11.5)));
+  }
+}
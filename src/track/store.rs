@@ -1,62 +1,221 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use walkdir::WalkDir;
 
 use super::{
+  cache::{CacheStats, SharedTrackFile, TrackCache},
+  codec::validate_point,
   entry::{TrackPoint, TrackPointCompact},
   error::{MetaFileError, TrackFileError},
-  interpolate::interpolate_track,
+  index::{TrackIndex, TrackSummary},
+  interpolate::{interpolate_at, interpolate_track},
+  journal::{Journal, JournalBatch, JournalTrack},
   metafile::{MetaBlock, MetaFile},
-  trackfile::TrackFile,
+  simplify::{simplify, Simplification},
+  spatial::{BoundingBox, SpatialIndex, TrackCrossing},
+  trackfile::{AppendReport, PointOutcome, TrackFile},
+  trackmeta::{meta_path, TrackMeta},
 };
-use crate::config::TrackConfig;
+use crate::config::{DedupConfig, RetentionConfig, TrackConfig};
+use chrono::Utc;
 use std::{
-  fs::create_dir_all,
+  collections::{HashMap, HashSet},
+  fs::{create_dir_all, remove_dir, File},
   path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, RwLock,
+  },
 };
 
 const SUBKEY_LENGTH: usize = 3;
 const NESTING_LEVEL: usize = 2;
+const INTERPOLATION_PADDING: usize = 2;
 
+/// Result of a scan of all track files
+#[derive(Debug, Default)]
+pub struct Inspection {
+  pub track_count: u64,
+  pub point_count: u64,
+  pub total_bytes: u64,
+  pub oldest_updated_at: u64,
+  pub newest_updated_at: u64,
+  /// paths of track files which can't be opened with the errors
+  pub corrupt: Vec<(String, String)>,
+  pub summaries: Vec<TrackSummary>,
+  // sizes of the summarized track files
+  sizes: HashMap<String, u64>,
+}
+
+impl Inspection {
+  // inspects a track file, the totals are computed by `tally`
+  fn add(&mut self, track_id: &str, path: &Path, size: u64) {
+    match TrackFile::open(path).and_then(|tf| summarize(track_id, &tf)) {
+      Ok(summary) => {
+        self.sizes.insert(track_id.to_owned(), size);
+        self.summaries.push(summary);
+      }
+      Err(err) => {
+        error!("TrackFile {} is corrupt: {err}", path.display());
+        self
+          .corrupt
+          .push((path.to_string_lossy().to_string(), err.to_string()));
+      }
+    }
+  }
+
+  // drops what is known of the tracks so that they can be inspected again
+  fn forget(&mut self, track_ids: &HashSet<String>) {
+    self
+      .summaries
+      .retain(|summary| !track_ids.contains(&summary.track_id));
+    self
+      .sizes
+      .retain(|track_id, _| !track_ids.contains(track_id));
+    self.corrupt.retain(|(path, _)| {
+      Path::new(path)
+        .file_stem()
+        .is_none_or(|stem| !track_ids.contains(&*stem.to_string_lossy()))
+    });
+  }
+
+  fn tally(&mut self) {
+    self.track_count = self.summaries.len() as u64;
+    self.point_count = self.summaries.iter().map(|summary| summary.count).sum();
+    self.total_bytes = self.sizes.values().sum();
+    let updated_at = self.summaries.iter().map(|summary| summary.updated_at);
+    self.oldest_updated_at = updated_at.clone().min().unwrap_or(0);
+    self.newest_updated_at = updated_at.max().unwrap_or(0);
+  }
+
+  fn apply(&self, block: &mut MetaBlock) {
+    block.track_count = self.track_count;
+    block.point_count = self.point_count;
+    block.total_bytes = self.total_bytes;
+    block.corrupt_count = self.corrupt.len() as u64;
+    block.oldest_updated_at = self.oldest_updated_at;
+    block.newest_updated_at = self.newest_updated_at;
+  }
+}
+
+/// Result of `TrackStore::reconcile`
 #[derive(Debug)]
+pub struct ReconcileReport {
+  /// the metablock replaced
+  pub previous: MetaBlock,
+  pub inspection: Inspection,
+}
+
+/// Counters of late points since startup
+#[derive(Debug, Default)]
+pub struct IngestStats {
+  /// points inserted before the latest point of their track
+  pub reordered: u64,
+  /// points dropped for being older than the reorder window
+  pub dropped: u64,
+}
+
+/// Result of `TrackStore::expire_tracks`
+#[derive(Debug, Default)]
+pub struct RetentionReport {
+  pub removed_tracks: u64,
+  pub removed_points: u64,
+  pub removed_bytes: u64,
+}
+
 pub struct TrackStore {
   folder: String,
-  metafile: MetaFile,
+  repair_on_open: bool,
+  reorder_window: u64,
+  dedup: DedupConfig,
+  metafile: Mutex<MetaFile>,
+  track_count: AtomicU64,
+  point_count: AtomicU64,
+  total_bytes: AtomicU64,
+  corrupt_count: AtomicU64,
+  oldest_updated_at: AtomicU64,
+  newest_updated_at: AtomicU64,
+  reordered_count: AtomicU64,
+  dropped_count: AtomicU64,
+  // the journal lock also serializes writers so that batches don't interleave
+  journal: Mutex<Journal>,
+  index: Mutex<TrackIndex>,
+  spatial: Mutex<SpatialIndex>,
+  cache: TrackCache,
+  // held for the whole reconcile so that only one runs at a time
+  reconcile: Mutex<()>,
+  // tracks changed by writers while a reconcile is scanning
+  dirty: Mutex<Option<HashSet<String>>>,
+}
+
+// track ids end up in file names, so they must not be able to leave
+// their hash directory
+fn check_track_id(track_id: &str) -> Result<(), TrackFileError> {
+  if track_id.is_empty() || track_id.contains(['/', '\\', '\0']) || track_id.contains("..") {
+    return Err(TrackFileError::InvalidTrackId(track_id.into()));
+  }
+  Ok(())
 }
 
-fn inspect_trackfiles_meta(folder: &str) -> (u64, u64) {
-  let mut tracks_count: u64 = 0;
-  let mut points_count: u64 = 0;
+// counters are only approximate until the next reconcile, they must not
+// wrap around when they drift below what is removed
+fn decrease(counter: &AtomicU64, n: u64) {
+  let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+    Some(v.saturating_sub(n))
+  });
+}
+
+// optional fields are emitted by compact tracks only when they change
+fn changed<T: PartialEq>(value: Option<T>, prev: Option<T>) -> Option<T> {
+  if value != prev {
+    value
+  } else {
+    None
+  }
+}
+
+fn inspect_trackfiles_meta(folder: &str) -> Inspection {
+  let mut res = Inspection::default();
 
   info!("Loading tracks metadata, this might take a while");
 
   for entry in WalkDir::new(folder).into_iter().flatten() {
     let md = entry.metadata();
     if let Ok(md) = md {
-      if md.is_file() {
-        let res = TrackFile::open(entry.path());
-        if let Ok(tf) = res {
-          let res = tf.count();
-          if let Err(err) = res {
-            error!(
-              "TrackFile {} is corrupt: {err}",
-              entry.file_name().to_str().unwrap()
-            );
-          } else {
-            tracks_count += 1;
-            points_count += res.unwrap();
-          }
-        }
-        if tracks_count % 5000 == 0 {
-          debug!("{tracks_count} tracks inspected")
+      let name = entry.file_name().to_string_lossy();
+      let track_id = name.strip_suffix(".bin");
+      if let (true, Some(track_id)) = (md.is_file(), track_id) {
+        res.add(track_id, entry.path(), md.len());
+        if res.summaries.len().is_multiple_of(5000) {
+          debug!("{} tracks inspected", res.summaries.len())
         }
       }
     }
   }
-  debug!("found {tracks_count} tracks with total {points_count} points");
-  (tracks_count, points_count)
+  res.tally();
+  debug!(
+    "found {} tracks with total {} points",
+    res.track_count, res.point_count
+  );
+  res
+}
+
+fn summarize(track_id: &str, tf: &TrackFile) -> Result<TrackSummary, TrackFileError> {
+  let range = tf.ts_range()?;
+  Ok(TrackSummary {
+    track_id: track_id.to_owned(),
+    count: tf.count()?,
+    first_ts: range.map(|(first, _)| first),
+    last_ts: range.map(|(_, last)| last),
+    updated_at: tf.get_header()?.timestamp(),
+  })
 }
 
-fn setup_meta(folder: &str) -> Result<MetaFile, MetaFileError> {
+// the scan of track files is shared by the metafile and the index
+// when both are missing
+fn setup_meta(
+  folder: &str,
+  inspection: &mut Option<Inspection>,
+) -> Result<MetaFile, MetaFileError> {
   let path = Path::new(folder).join(".meta");
   let res = MetaFile::open(&path);
   match res {
@@ -64,10 +223,9 @@ fn setup_meta(folder: &str) -> Result<MetaFile, MetaFileError> {
     Err(err) => match err {
       MetaFileError::NotFound(_) => {
         let mut mf = MetaFile::create(&path)?;
-        let (track_count, point_count) = inspect_trackfiles_meta(folder);
+        let inspection = inspection.get_or_insert_with(|| inspect_trackfiles_meta(folder));
         let mut block = mf.read_block()?;
-        block.track_count = track_count;
-        block.point_count = point_count;
+        inspection.apply(&mut block);
         mf.write_block(&block)?;
         Ok(mf)
       }
@@ -76,17 +234,77 @@ fn setup_meta(folder: &str) -> Result<MetaFile, MetaFileError> {
   }
 }
 
+fn setup_index(
+  folder: &str,
+  inspection: &mut Option<Inspection>,
+) -> Result<TrackIndex, MetaFileError> {
+  let path = Path::new(folder).join(".index");
+  if let Some(index) = TrackIndex::open(&path)? {
+    return Ok(index);
+  }
+  info!("building the track index");
+  let inspection = inspection.get_or_insert_with(|| inspect_trackfiles_meta(folder));
+  Ok(TrackIndex::create(&path, inspection.summaries.clone())?)
+}
+
+// unlike the track index the spatial one needs every point of every track,
+// so it's built by a separate scan
+fn setup_spatial(folder: &str) -> Result<SpatialIndex, MetaFileError> {
+  let path = Path::new(folder).join(".spatial");
+  if let Some(index) = SpatialIndex::open(&path)? {
+    return Ok(index);
+  }
+  info!("building the spatial index, this might take a while");
+  let mut index = SpatialIndex::create(&path)?;
+  for entry in WalkDir::new(folder).into_iter().flatten() {
+    let name = entry.file_name().to_string_lossy();
+    let Some(track_id) = name.strip_suffix(".bin") else {
+      continue;
+    };
+    match TrackFile::open(entry.path()).and_then(|tf| tf.read_all()) {
+      Ok(points) => index.add(track_id, &points)?,
+      Err(err) => warn!("skipping track file {name}: {err}"),
+    }
+  }
+  index.sync()?;
+  Ok(index)
+}
+
 impl TrackStore {
   pub fn new(cfg: &TrackConfig) -> Result<Self, MetaFileError> {
-    let metafile = setup_meta(&cfg.folder)?;
+    let mut inspection = None;
+    let mut metafile = setup_meta(&cfg.folder, &mut inspection)?;
+    let index = setup_index(&cfg.folder, &mut inspection)?;
+    let spatial = setup_spatial(&cfg.folder)?;
+    let metablock = metafile.read_block()?;
+    let journal = Journal::open(Path::new(&cfg.folder).join(".journal"))?;
     let ts = Self {
       folder: cfg.folder.clone(),
-      metafile,
+      repair_on_open: cfg.repair_on_open,
+      reorder_window: cfg.reorder_window,
+      dedup: cfg.dedup,
+      metafile: Mutex::new(metafile),
+      track_count: AtomicU64::new(metablock.track_count),
+      point_count: AtomicU64::new(metablock.point_count),
+      total_bytes: AtomicU64::new(metablock.total_bytes),
+      corrupt_count: AtomicU64::new(metablock.corrupt_count),
+      oldest_updated_at: AtomicU64::new(metablock.oldest_updated_at),
+      newest_updated_at: AtomicU64::new(metablock.newest_updated_at),
+      reordered_count: AtomicU64::new(0),
+      dropped_count: AtomicU64::new(0),
+      journal: Mutex::new(journal),
+      index: Mutex::new(index),
+      spatial: Mutex::new(spatial),
+      cache: TrackCache::new(cfg.cache_size),
+      reconcile: Mutex::new(()),
+      dirty: Mutex::new(None),
     };
+    ts.replay_journal()?;
     Ok(ts)
   }
 
-  fn target_directory(&self, track_id: &str) -> PathBuf {
+  fn target_directory(&self, track_id: &str) -> Result<PathBuf, TrackFileError> {
+    check_track_id(track_id)?;
     let mut path = PathBuf::from(&self.folder);
     let hash = md5::compute(track_id);
     let hash = format!("{:x}", hash);
@@ -95,84 +313,798 @@ impl TrackStore {
       let subkey = &hash[i * SUBKEY_LENGTH..(i + 1) * SUBKEY_LENGTH];
       path = path.join(subkey);
     }
-    path
+    Ok(path)
   }
 
-  pub fn get_metablock(&mut self) -> Result<MetaBlock, MetaFileError> {
-    self.metafile.read_block()
+  pub fn get_metablock(&self) -> Result<MetaBlock, MetaFileError> {
+    Ok(MetaBlock {
+      track_count: self.track_count.load(Ordering::Relaxed),
+      point_count: self.point_count.load(Ordering::Relaxed),
+      updated_at: Utc::now().timestamp_millis() as u64,
+      total_bytes: self.total_bytes.load(Ordering::Relaxed),
+      corrupt_count: self.corrupt_count.load(Ordering::Relaxed),
+      oldest_updated_at: self.oldest_updated_at.load(Ordering::Relaxed),
+      newest_updated_at: self.newest_updated_at.load(Ordering::Relaxed),
+    })
   }
 
-  fn inc_track_count(&mut self) {
-    let res = self.metafile.read_block();
-    if let Err(err) = res {
-      error!("error reading metablock: {err}");
+  fn set_metablock(&self, block: &MetaBlock) {
+    self.track_count.store(block.track_count, Ordering::Relaxed);
+    self.point_count.store(block.point_count, Ordering::Relaxed);
+    self.total_bytes.store(block.total_bytes, Ordering::Relaxed);
+    self
+      .corrupt_count
+      .store(block.corrupt_count, Ordering::Relaxed);
+    self
+      .oldest_updated_at
+      .store(block.oldest_updated_at, Ordering::Relaxed);
+    self
+      .newest_updated_at
+      .store(block.newest_updated_at, Ordering::Relaxed);
+  }
+
+  // accounts a write which changed the size of a track file
+  fn track_written(&self, size_before: u64, size_after: u64) {
+    if size_after >= size_before {
+      self
+        .total_bytes
+        .fetch_add(size_after - size_before, Ordering::Relaxed);
     } else {
-      let mut block = res.unwrap();
-      block.track_count += 1;
-      let res = self.metafile.write_block(&block);
-      if let Err(err) = res {
-        error!("error writing metablock: {err}");
+      decrease(&self.total_bytes, size_before - size_after);
+    }
+    let now = Utc::now().timestamp_millis() as u64;
+    self.newest_updated_at.fetch_max(now, Ordering::Relaxed);
+    // until the next scan the first track written is the oldest known one
+    let _ = self
+      .oldest_updated_at
+      .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+  }
+
+  /// Recounts tracks and points by scanning all track files and replaces
+  /// the counters with the result. Writers aren't blocked during the scan,
+  /// the tracks they change meanwhile are inspected again once it's over.
+  pub fn reconcile(&self) -> Result<ReconcileReport, MetaFileError> {
+    let _reconcile = self.reconcile.lock().unwrap();
+    {
+      // writers mark the tracks under the journal lock, so none of them
+      // can be halfway through a batch when the scan starts
+      let _journal = self.journal.lock().unwrap();
+      *self.dirty.lock().unwrap() = Some(HashSet::new());
+    }
+    let mut inspection = inspect_trackfiles_meta(&self.folder);
+
+    let _journal = self.journal.lock().unwrap();
+    let dirty = self.dirty.lock().unwrap().take().unwrap_or_default();
+    inspection.forget(&dirty);
+    for track_id in dirty.iter() {
+      let Ok(path) = self.track_path(track_id) else {
+        continue;
+      };
+      if let Ok(md) = std::fs::metadata(&path) {
+        inspection.add(track_id, &path, md.len());
       }
     }
+    inspection.tally();
+    let previous = self.get_metablock()?;
+    let mut block = previous.clone();
+    inspection.apply(&mut block);
+    self.set_metablock(&block);
+    self.sync_metablock()?;
+    self
+      .index
+      .lock()
+      .unwrap()
+      .rebuild(inspection.summaries.clone())?;
+    let existing: HashSet<&str> = inspection
+      .summaries
+      .iter()
+      .map(|summary| summary.track_id.as_str())
+      .collect();
+    self
+      .spatial
+      .lock()
+      .unwrap()
+      .retain(|track_id| existing.contains(track_id))?;
+    Ok(ReconcileReport {
+      previous,
+      inspection,
+    })
+  }
+
+  // records a track changed by a writer holding the journal lock
+  fn mark_dirty(&self, track_id: &str) {
+    if let Some(dirty) = self.dirty.lock().unwrap().as_mut() {
+      dirty.insert(track_id.to_owned());
+    }
   }
 
-  fn inc_point_count(&mut self) {
-    let res = self.metafile.read_block();
+  // records the current state of written tracks in the index
+  fn write_index(&self, tracks: &[(&str, &TrackFile)]) {
+    let res = tracks
+      .iter()
+      .map(|(track_id, tf)| summarize(track_id, tf))
+      .collect::<Result<Vec<_>, _>>()
+      .and_then(|summaries| {
+        let mut index = self.index.lock().unwrap();
+        index.update(summaries)?;
+        Ok(index.sync()?)
+      });
     if let Err(err) = res {
-      error!("error reading metablock: {err}");
-    } else {
-      let mut block = res.unwrap();
-      block.point_count += 1;
-      let res = self.metafile.write_block(&block);
-      if let Err(err) = res {
-        error!("error writing metablock: {err}");
+      error!("error writing track index: {err}");
+    }
+  }
+
+  // adds the cells of written points to the spatial index
+  fn write_spatial(&self, tracks: &[(&str, &[TrackPoint])]) {
+    let mut spatial = self.spatial.lock().unwrap();
+    let res = tracks
+      .iter()
+      .try_for_each(|(track_id, points)| spatial.add(track_id, points))
+      .and_then(|_| spatial.sync());
+    if let Err(err) = res {
+      error!("error writing spatial index: {err}");
+    }
+  }
+
+  fn write_metablock(&self) {
+    let res = self.sync_metablock();
+    if let Err(err) = res {
+      error!("error writing metablock: {err}");
+    }
+  }
+
+  fn sync_metablock(&self) -> Result<(), MetaFileError> {
+    let block = self.get_metablock()?;
+    self.metafile.lock().unwrap().write_block(&block)
+  }
+
+  pub fn cache_stats(&self) -> CacheStats {
+    self.cache.stats()
+  }
+
+  pub fn ingest_stats(&self) -> IngestStats {
+    IngestStats {
+      reordered: self.reordered_count.load(Ordering::Relaxed),
+      dropped: self.dropped_count.load(Ordering::Relaxed),
+    }
+  }
+
+  fn count_late(&self, report: &AppendReport) {
+    let reordered = report.count(&PointOutcome::Reordered) as u64;
+    let dropped = report.count(&PointOutcome::Dropped) as u64;
+    if dropped > 0 {
+      debug!("{dropped} late points dropped");
+    }
+    self.reordered_count.fetch_add(reordered, Ordering::Relaxed);
+    self.dropped_count.fetch_add(dropped, Ordering::Relaxed);
+  }
+
+  fn open_path(&self, path: &Path) -> Result<TrackFile, TrackFileError> {
+    let res = TrackFile::open(path);
+    let res = match res {
+      Err(err) if self.repair_on_open && Self::is_repairable(&err) => {
+        Self::repair_path(path, &err)?;
+        TrackFile::open(path)
+      }
+      _ => res,
+    };
+    let mut tf = res?;
+    self.configure(&mut tf);
+    Ok(tf)
+  }
+
+  // whether a track file failing to open with `err` can be repaired
+  fn is_repairable(err: &TrackFileError) -> bool {
+    matches!(
+      err,
+      TrackFileError::InvalidFileLength(_, _)
+        | TrackFileError::CountMismatch(_, _)
+        | TrackFileError::InvalidBlock(_)
+    )
+  }
+
+  fn repair_path(path: &Path, err: &TrackFileError) -> Result<(), TrackFileError> {
+    warn!("track file {} is damaged: {err}, repairing", path.display());
+    let report = TrackFile::repair(path)?;
+    info!(
+      "track file {} repaired: {} of {} points recovered, {} bytes truncated",
+      path.display(),
+      report.count,
+      report.header_count,
+      report.truncated_bytes
+    );
+    Ok(())
+  }
+
+  fn configure(&self, tf: &mut TrackFile) {
+    tf.set_reorder_window(self.reorder_window);
+    tf.set_dedup_tolerance(self.dedup);
+  }
+
+  fn track_path(&self, track_id: &str) -> Result<PathBuf, TrackFileError> {
+    Ok(
+      self
+        .target_directory(track_id)?
+        .join(format!("{track_id}.bin")),
+    )
+  }
+
+  fn create_file(&self, track_id: &str) -> Result<TrackFile, TrackFileError> {
+    let target_dir = self.target_directory(track_id)?;
+    create_dir_all(&target_dir)?;
+    let mut tf = TrackFile::create(self.track_path(track_id)?)?;
+    self.configure(&mut tf);
+    // make sure the new directory entry survives a crash
+    File::open(&target_dir)?.sync_all()?;
+    Ok(tf)
+  }
+
+  // returns the track file handle and whether the file has been created
+  fn open_or_create(&self, track_id: &str) -> Result<(SharedTrackFile, bool), TrackFileError> {
+    let mut created = false;
+    let tf = self.cache.get_or_open(track_id, || {
+      match self.open_path(&self.track_path(track_id)?) {
+        Err(TrackFileError::NotFound(_)) => {
+          created = true;
+          self.create_file(track_id)
+        }
+        res => res,
       }
+    })?;
+    Ok((tf, created))
+  }
+
+  fn open(&self, track_id: &str) -> Result<SharedTrackFile, TrackFileError> {
+    self
+      .cache
+      .get_or_open(track_id, || self.open_path(&self.track_path(track_id)?))
+  }
+
+  /// Appends a batch of points in a crash-safe manner.
+  ///
+  /// The batch is persisted to the journal first, then applied to the track
+  /// files, which are synced to disk along with the updated metablock, and
+  /// only then the journal is cleared. A batch interrupted by a crash is
+  /// replayed by `TrackStore::new`. Points are grouped by track so that each
+  /// track file is written once.
+  ///
+  /// Returns the outcomes in the order of `entries`. Invalid points are
+  /// rejected before the batch is journaled, the points of a track which
+  /// can't be opened or written are reported as failed. An error is
+  /// only returned if the batch as a whole can't be persisted.
+  pub fn append_batch(
+    &self,
+    entries: &[(&str, &TrackPoint)],
+  ) -> Result<Vec<PointOutcome>, TrackFileError> {
+    let mut journal = self.journal.lock().unwrap();
+    let mut outcomes = vec![PointOutcome::Appended; entries.len()];
+    let mut files: Vec<Result<Option<SharedTrackFile>, String>> = vec![];
+    let mut groups: Vec<Vec<TrackPoint>> = vec![];
+    let mut positions: Vec<Vec<usize>> = vec![];
+    let mut indices = HashMap::new();
+    let mut batch = JournalBatch {
+      meta: self.get_metablock().unwrap(),
+      tracks: vec![],
+      points: vec![],
+    };
+
+    for (pos, (track_id, point)) in entries.iter().enumerate() {
+      // only valid points are journaled, no track is created for invalid ones
+      if let Err(err) = check_track_id(track_id) {
+        outcomes[pos] = PointOutcome::Rejected(format!("{err}"));
+        continue;
+      }
+      if let Err(err) = validate_point(point) {
+        outcomes[pos] = PointOutcome::Rejected(format!("{err}"));
+        continue;
+      }
+      let idx = match indices.get(track_id) {
+        Some(idx) => *idx,
+        None => {
+          let tf = match self.open(track_id) {
+            Ok(tf) => Ok(Some(tf)),
+            Err(TrackFileError::NotFound(_)) => Ok(None),
+            Err(err) => Err(format!("{err}")),
+          };
+          let (count, size) = match &tf {
+            Ok(Some(tf)) => {
+              let tf = tf.read().unwrap();
+              (Some(tf.count()?), tf.size())
+            }
+            _ => (None, 0),
+          };
+          self.mark_dirty(track_id);
+          batch.tracks.push(JournalTrack {
+            track_id: track_id.to_string(),
+            count,
+            size,
+          });
+          files.push(tf);
+          groups.push(vec![]);
+          positions.push(vec![]);
+          indices.insert(track_id, files.len() - 1);
+          files.len() - 1
+        }
+      };
+      batch.points.push((idx, (*point).clone()));
+      groups[idx].push((*point).clone());
+      positions[idx].push(pos);
+    }
+
+    journal.write(&batch)?;
+
+    for (idx, points) in groups.iter().enumerate() {
+      let opened = match &mut files[idx] {
+        Ok(Some(tf)) => Ok(&*tf),
+        Ok(slot) => match self.open_or_create(&batch.tracks[idx].track_id) {
+          Ok((tf, created)) => {
+            if created {
+              self.track_count.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(&*slot.insert(tf))
+          }
+          Err(err) => Err(format!("{err}")),
+        },
+        Err(err) => Err(err.clone()),
+      };
+      let tf = match opened {
+        Ok(tf) => tf,
+        Err(err) => {
+          for pos in positions[idx].iter() {
+            outcomes[*pos] = PointOutcome::Failed(err.clone());
+          }
+          continue;
+        }
+      };
+
+      let mut tf = tf.write().unwrap();
+      let count = batch.tracks[idx].count.unwrap_or(0);
+      let appended = tf.append_many(points);
+      self
+        .point_count
+        .fetch_add(tf.count()?.saturating_sub(count), Ordering::Relaxed);
+      self.track_written(batch.tracks[idx].size, tf.size());
+      match appended {
+        Ok(report) => {
+          self.count_late(&report);
+          for (pos, outcome) in positions[idx].iter().zip(report.outcomes) {
+            outcomes[*pos] = outcome;
+          }
+        }
+        Err(err) => {
+          for pos in positions[idx].iter() {
+            outcomes[*pos] = PointOutcome::Failed(format!("{err}"));
+          }
+        }
+      }
+    }
+
+    let written: Vec<_> = batch
+      .tracks
+      .iter()
+      .zip(files.iter())
+      .filter_map(|(track, tf)| {
+        let tf = tf.as_ref().ok()?.as_ref()?;
+        Some((track.track_id.as_str(), tf.read().unwrap()))
+      })
+      .collect();
+    for (_, tf) in written.iter() {
+      tf.sync()?;
     }
+    let tracks: Vec<(&str, &TrackFile)> = written.iter().map(|(id, tf)| (*id, &**tf)).collect();
+    self.write_index(&tracks);
+    drop(written);
+    let stored: Vec<Vec<TrackPoint>> = positions
+      .iter()
+      .map(|positions| {
+        positions
+          .iter()
+          .filter(|pos| outcomes[**pos].is_accepted())
+          .map(|pos| entries[*pos].1.clone())
+          .collect()
+      })
+      .collect();
+    let points: Vec<(&str, &[TrackPoint])> = batch
+      .tracks
+      .iter()
+      .zip(stored.iter())
+      .map(|(track, points)| (track.track_id.as_str(), points.as_slice()))
+      .collect();
+    self.write_spatial(&points);
+    self.write_metablock();
+    journal.clear()?;
+    Ok(outcomes)
   }
 
-  fn open_or_create(&mut self, track_id: &str) -> Result<TrackFile, TrackFileError> {
-    let target_dir = self.target_directory(track_id);
-    let path = target_dir.join(format!("{track_id}.bin"));
-    let res = TrackFile::open(&path);
+  fn replay_journal(&self) -> Result<(), std::io::Error> {
+    let mut journal = self.journal.lock().unwrap();
+    let batch = match journal.read()? {
+      Some(batch) => batch,
+      None => return Ok(()),
+    };
+
+    warn!(
+      "replaying an interrupted batch of {} points in {} tracks",
+      batch.points.len(),
+      batch.tracks.len()
+    );
+
+    let mut groups: Vec<Vec<TrackPoint>> = batch.tracks.iter().map(|_| vec![]).collect();
+    for (idx, point) in batch.points.iter() {
+      groups[*idx].push(point.clone());
+    }
 
-    match res {
-      Ok(tf) => Ok(tf),
-      Err(err) => match err {
-        TrackFileError::NotFound(_) => {
-          create_dir_all(target_dir)?;
-          let tf = TrackFile::create(&path)?;
-          self.inc_track_count();
-          Ok(tf)
+    let mut files: Vec<Option<SharedTrackFile>> = batch.tracks.iter().map(|_| None).collect();
+    for (idx, points) in groups.iter().enumerate() {
+      let track_id = &batch.tracks[idx].track_id;
+      // a crash may have left the batch half written, such tracks are
+      // repaired regardless of repair_on_open so that the batch isn't lost
+      let mut repaired = false;
+      let res = match self.open_or_create(track_id) {
+        Err(err) if Self::is_repairable(&err) => self
+          .track_path(track_id)
+          .and_then(|path| Self::repair_path(&path, &err))
+          .and_then(|_| {
+            repaired = true;
+            self.open_or_create(track_id)
+          }),
+        res => res,
+      };
+      let tf = match res {
+        Ok((tf, _)) => files[idx].insert(tf),
+        Err(err) => {
+          error!("error replaying journal for track {track_id}: {err}");
+          continue;
         }
-        _ => Err(err),
-      },
+      };
+
+      let mut tf = tf.write().unwrap();
+      let track = &batch.tracks[idx];
+      // a track left as it was before the batch gets all of its points, late
+      // ones included, otherwise the points which made it to the track before
+      // the crash are skipped. Repairs rewrite the file, so only the count
+      // tells whether the batch made it to a repaired track.
+      let res = tf.count().and_then(|count| {
+        let untouched = match track.count {
+          Some(before) => before == count && (repaired || track.size == tf.size()),
+          None => count == 0,
+        };
+        let last_ts = tf.last()?.map(|last| last.ts);
+        let points: Vec<TrackPoint> = points
+          .iter()
+          .filter(|p| untouched || last_ts.is_none_or(|ts| p.ts > ts))
+          .cloned()
+          .collect();
+        tf.append_many(&points)
+      });
+      match res {
+        Ok(report) => self.count_late(&report),
+        Err(err) => error!("error replaying journal for track {track_id}: {err}"),
+      }
     }
+
+    // counters are recomputed from the state preceding the batch
+    let mut block = batch.meta;
+    let mut written = vec![];
+    for (track, tf) in batch.tracks.iter().zip(files.iter()) {
+      let Some(tf) = tf else { continue };
+      let tf = tf.read().unwrap();
+      tf.sync()
+        .map_err(|err| std::io::Error::other(err.to_string()))?;
+      let count = tf.count().unwrap_or(0);
+      let size = tf.size();
+      match track.count {
+        Some(before) => block.point_count = (block.point_count + count).saturating_sub(before),
+        None => {
+          block.track_count += 1;
+          block.point_count += count;
+        }
+      }
+      block.total_bytes = (block.total_bytes + size).saturating_sub(track.size);
+      written.push((track.track_id.as_str(), tf));
+    }
+    let tracks: Vec<(&str, &TrackFile)> = written.iter().map(|(id, tf)| (*id, &**tf)).collect();
+    self.write_index(&tracks);
+    drop(written);
+    // cells are indexed once per track, re-adding the applied points is a no-op
+    let points: Vec<(&str, &[TrackPoint])> = batch
+      .tracks
+      .iter()
+      .zip(groups.iter())
+      .map(|(track, points)| (track.track_id.as_str(), points.as_slice()))
+      .collect();
+    self.write_spatial(&points);
+    block.newest_updated_at = Utc::now().timestamp_millis() as u64;
+    self.set_metablock(&block);
+    self.write_metablock();
+    journal.clear()
   }
 
-  fn open(&self, track_id: &str) -> Result<TrackFile, TrackFileError> {
-    let path = self.target_directory(track_id);
-    let path = path.join(format!("{track_id}.bin"));
-    TrackFile::open(path)
+  /// Removes a track with its metadata, updating the counters and pruning
+  /// hash directories left empty. Corrupt tracks are removed too.
+  pub fn remove_track(&self, track_id: &str) -> Result<(), TrackFileError> {
+    self.remove_track_if_stale(track_id, None).map(|_| ())
   }
 
-  pub fn append(
-    &mut self,
+  // removes the track unless it has been updated after `updated_at`,
+  // returns the number of points removed
+  fn remove_track_if_stale(
+    &self,
     track_id: &str,
-    entry: &TrackPoint,
-    create_if_not_exist: bool,
-  ) -> Result<(), TrackFileError> {
-    let mut tf = if create_if_not_exist {
-      self.open_or_create(track_id)?
-    } else {
-      self.open(track_id)?
+    updated_at: Option<u64>,
+  ) -> Result<Option<u64>, TrackFileError> {
+    let path = self.track_path(track_id)?;
+    let _journal = self.journal.lock().unwrap();
+    self.mark_dirty(track_id);
+    let summary = self.index.lock().unwrap().get(track_id).cloned();
+    // (points, bytes, whether the track was counted as a valid one)
+    let removed = self.cache.remove_with(track_id, |cached| {
+      let tf = match cached {
+        Some(tf) => tf,
+        None => match self.open_path(&path) {
+          Ok(tf) => Arc::new(RwLock::new(tf)),
+          // only explicit deletes get rid of files which can't be opened,
+          // they were counted as corrupt unless they broke after indexing
+          Err(err) if updated_at.is_none() && err.is_corruption() => {
+            warn!("removing corrupt track {track_id}: {err}");
+            let size = std::fs::metadata(&path)?.len();
+            std::fs::remove_file(&path)?;
+            match std::fs::remove_file(meta_path(&path)) {
+              Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+              _ => {}
+            }
+            return Ok(Some(match summary.as_ref() {
+              Some(summary) => (summary.count, size, true),
+              None => (0, 0, false),
+            }));
+          }
+          Err(err) => return Err(err),
+        },
+      };
+      let tf = tf.write().unwrap();
+      let header = tf.get_header()?;
+      if updated_at.is_some_and(|ts| header.timestamp() > ts) {
+        return Ok(None);
+      }
+      let count = tf.count()?;
+      let size = tf.size();
+      tf.destroy()?;
+      Ok(Some((count, size, true)))
+    })?;
+    let Some((count, size, counted)) = removed else {
+      return Ok(None);
     };
 
-    let appended = tf.append(entry)?;
-    if appended {
-      self.inc_point_count();
+    if counted {
+      decrease(&self.track_count, 1);
+      decrease(&self.point_count, count);
+      decrease(&self.total_bytes, size);
+    } else {
+      decrease(&self.corrupt_count, 1);
+    }
+    if let Err(err) = self.index.lock().unwrap().remove(track_id) {
+      error!("error removing track {track_id} from the index: {err}");
+    }
+    if let Err(err) = self.spatial.lock().unwrap().remove(track_id) {
+      error!("error removing track {track_id} from the spatial index: {err}");
     }
+    self.write_metablock();
 
-    Ok(())
+    // writers hold the journal lock too, so no track file can be created
+    // in these directories until they are checked
+    let mut dir = self.target_directory(track_id)?;
+    for _ in 0..NESTING_LEVEL {
+      if remove_dir(&dir).is_err() {
+        break;
+      }
+      dir.pop();
+    }
+    Ok(Some(count))
+  }
+
+  /// Drops the points of a track with a ts greater than `after`, returns
+  /// the number of points removed
+  pub fn truncate_track(&self, track_id: &str, after: i64) -> Result<u64, TrackFileError> {
+    let _journal = self.journal.lock().unwrap();
+    self.mark_dirty(track_id);
+    let tf = self.open(track_id)?;
+    let mut tf = tf.write().unwrap();
+    let size = tf.size();
+    let removed = tf.truncate_after(after)?;
+    if removed > 0 {
+      tf.sync()?;
+      decrease(&self.point_count, removed);
+      self.track_written(size, tf.size());
+      self.write_index(&[(track_id, &tf)]);
+      self.write_metablock();
+    }
+    Ok(removed)
+  }
+
+  /// Drops the points with a ts greater than `after` from every track
+  /// updated at or after `since` (unix time in ms). Returns the number of
+  /// tracks changed and the number of points removed.
+  pub fn truncate_tracks(&self, since: u64, after: i64) -> Result<(u64, u64), TrackFileError> {
+    let mut tracks = 0;
+    let mut points = 0;
+    let candidates = self.index.lock().unwrap().by_update();
+    for (track_id, updated_at) in candidates {
+      if updated_at < since {
+        continue;
+      }
+      match self.truncate_track(&track_id, after) {
+        Ok(0) => (),
+        Ok(removed) => {
+          tracks += 1;
+          points += removed;
+        }
+        Err(err) => error!("error truncating track {track_id}: {err}"),
+      }
+    }
+    Ok((tracks, points))
+  }
+
+  /// Removes the tracks which are older than the retention policy allows,
+  /// then the least recently updated ones while the total size of track
+  /// files is over the limit
+  pub fn expire_tracks(
+    &self,
+    retention: &RetentionConfig,
+  ) -> Result<RetentionReport, TrackFileError> {
+    // candidates come from the index so that track files are only stat'ed,
+    // the ones unknown to it are left to reconcile
+    let tracks: Vec<(String, u64, u64)> = self
+      .index
+      .lock()
+      .unwrap()
+      .by_update()
+      .into_iter()
+      .filter_map(|(track_id, updated_at)| {
+        let path = self.track_path(&track_id).ok()?;
+        let size = std::fs::metadata(path).ok()?.len();
+        Some((track_id, updated_at, size))
+      })
+      .collect();
+
+    let now = Utc::now().timestamp_millis() as u64;
+    let expire_before = retention
+      .max_age
+      .map(|age| now.saturating_sub(age * 1000))
+      .unwrap_or(0);
+    let mut total_size: u64 = tracks.iter().map(|(_, _, size)| size).sum();
+    let max_size = retention.max_size.unwrap_or(u64::MAX);
+
+    let mut report = RetentionReport::default();
+    let mut oldest = None;
+    for (track_id, updated_at, size) in tracks.iter() {
+      if *updated_at >= expire_before && total_size <= max_size {
+        oldest.get_or_insert(*updated_at);
+        break;
+      }
+      match self.remove_track_if_stale(track_id, Some(*updated_at)) {
+        Ok(Some(points)) => {
+          total_size -= size;
+          report.removed_tracks += 1;
+          report.removed_points += points;
+          report.removed_bytes += size;
+        }
+        Ok(None) => {
+          oldest.get_or_insert(*updated_at);
+        }
+        Err(err) => {
+          oldest.get_or_insert(*updated_at);
+          error!("error removing track {track_id}: {err}");
+        }
+      }
+    }
+
+    // the scan is a chance to refresh the oldest update time
+    self
+      .oldest_updated_at
+      .store(oldest.unwrap_or(0), Ordering::Relaxed);
+    self.write_metablock();
+    Ok(report)
+  }
+
+  /// Lists the indexed tracks sorted by id, see `TrackIndex::list`
+  pub fn list_tracks(
+    &self,
+    prefix: Option<&str>,
+    updated_since: Option<u64>,
+    limit: usize,
+    cursor: Option<&str>,
+  ) -> (Vec<TrackSummary>, Option<String>) {
+    let index = self.index.lock().unwrap();
+    index.list(prefix, updated_since, limit, cursor)
+  }
+
+  /// Finds the tracks with points inside the box with a ts within
+  /// `after..before`, exclusive like in `load_track`. A track crossing the box
+  /// several times has a crossing per visit, its timestamps are those of the
+  /// first and the last points inside the box.
+  pub fn search_tracks(
+    &self,
+    bbox: &BoundingBox,
+    after: i64,
+    before: i64,
+  ) -> Result<Vec<TrackCrossing>, TrackFileError> {
+    let candidates = self.spatial.lock().unwrap().candidates(bbox, after, before);
+    let mut res = vec![];
+    for track_id in candidates.into_iter() {
+      let points = match self.load_track(&track_id, false, Some(after), Some(before), None) {
+        Ok(points) => points,
+        // removed after the candidates have been collected
+        Err(TrackFileError::NotFound(_)) => continue,
+        Err(err) => return Err(err),
+      };
+      let mut current: Option<TrackCrossing> = None;
+      for point in points.iter() {
+        match (bbox.contains(point), current.as_mut()) {
+          (true, Some(crossing)) => crossing.left_at = point.ts,
+          (true, None) => {
+            current = Some(TrackCrossing {
+              track_id: track_id.clone(),
+              entered_at: point.ts,
+              left_at: point.ts,
+            })
+          }
+          (false, _) => res.extend(current.take()),
+        }
+      }
+      res.extend(current);
+    }
+    Ok(res)
+  }
+
+  /// Returns the positions of all tracks active at `ts`, interpolated
+  /// between the surrounding points. Tracks which were silent for longer
+  /// than the dedup `max_gap` around `ts` are left out.
+  pub fn snapshot(&self, ts: i64) -> Result<Vec<(String, TrackPoint)>, TrackFileError> {
+    let max_gap = self
+      .dedup
+      .max_gap
+      .map(|secs| secs.saturating_mul(1000).min(i64::MAX as u64) as i64);
+    let active = self.index.lock().unwrap().active_at(ts);
+    let mut res = vec![];
+    for track_id in active.into_iter() {
+      let tf = match self.open(&track_id) {
+        Ok(tf) => tf,
+        // removed after the index has been checked
+        Err(TrackFileError::NotFound(_)) => continue,
+        Err(err) => return Err(err),
+      };
+      let tf = tf.read().unwrap();
+      let next = tf.find_range(Some(ts), None)?.start;
+      let start = next.saturating_sub(INTERPOLATION_PADDING);
+      let points = tf.read_multiple_at(start, next + INTERPOLATION_PADDING - start)?;
+      if let Some(point) = interpolate_at(&points, ts, max_gap) {
+        res.push((track_id, point));
+      }
+    }
+    Ok(res)
+  }
+
+  pub fn load_track_meta(&self, track_id: &str) -> Result<TrackMeta, TrackFileError> {
+    let tf = self.open(track_id)?;
+    let tf = tf.read().unwrap();
+    tf.read_meta()
+  }
+
+  /// Merges `update` into the metadata of an existing track
+  pub fn update_track_meta(
+    &self,
+    track_id: &str,
+    update: &TrackMeta,
+  ) -> Result<TrackMeta, TrackFileError> {
+    let tf = self.open(track_id)?;
+    let mut tf = tf.write().unwrap();
+    tf.update_meta(update)
+  }
+
+  pub fn verify_track(&self, track_id: &str) -> Result<Option<usize>, TrackFileError> {
+    let tf = self.open(track_id)?;
+    let tf = tf.read().unwrap();
+    tf.verify()
   }
 
   pub fn load_track(
@@ -180,23 +1112,36 @@ impl TrackStore {
     track_id: &str,
     interpolate: bool,
     after: Option<i64>,
+    before: Option<i64>,
+    simplification: Option<Simplification>,
   ) -> Result<Vec<TrackPoint>, TrackFileError> {
     let tf = self.open(track_id)?;
-    let points = tf.read_all()?;
-    let points = if interpolate {
-      interpolate_track(&points)
-    } else {
-      points
-    };
+    let tf = tf.read().unwrap();
+    let range = tf.find_range(after, before)?;
 
-    // TODO: stop reading the entire file when `after` is set
-    let points = if let Some(after) = after {
-      points.into_iter().filter(|p| p.ts > after).collect()
-    } else {
-      points
-    };
+    if !interpolate {
+      let points = tf.read_multiple_at(range.start, range.len())?;
+      return Ok(match simplification {
+        Some(tolerance) => simplify(&points, &tolerance),
+        None => points,
+      });
+    }
 
-    Ok(points)
+    // splines need neighbouring points to sample the edges of the range,
+    // so a few extra points are read on both sides and cut off afterwards
+    let start = range.start.saturating_sub(INTERPOLATION_PADDING);
+    let len = range.end + INTERPOLATION_PADDING - start;
+    let points = tf.read_multiple_at(start, len)?;
+    let points: Vec<TrackPoint> = interpolate_track(&points)
+      .into_iter()
+      .filter(|p| after.map(|after| p.ts > after).unwrap_or(true))
+      .filter(|p| before.map(|before| p.ts < before).unwrap_or(true))
+      .collect();
+
+    Ok(match simplification {
+      Some(tolerance) => simplify(&points, &tolerance),
+      None => points,
+    })
   }
 
   pub fn load_track_compact(
@@ -204,8 +1149,10 @@ impl TrackStore {
     track_id: &str,
     interpolate: bool,
     after: Option<i64>,
+    before: Option<i64>,
+    simplification: Option<Simplification>,
   ) -> Result<Vec<TrackPointCompact>, TrackFileError> {
-    let points = self.load_track(track_id, interpolate, after)?;
+    let points = self.load_track(track_id, interpolate, after, before, simplification)?;
     let mut compact = vec![];
     if !points.is_empty() {
       let mut curr = points.first().unwrap();
@@ -216,6 +1163,10 @@ impl TrackStore {
         hdg: Some(curr.hdg),
         alt: Some(curr.alt),
         gs: Some(curr.gs),
+        vs: curr.vs,
+        squawk: curr.squawk,
+        on_ground: curr.on_ground,
+        qnh: curr.qnh,
       });
 
       for point in points[1..].iter() {
@@ -252,6 +1203,10 @@ impl TrackStore {
           hdg,
           alt,
           gs,
+          vs: changed(point.vs, curr.vs),
+          squawk: changed(point.squawk, curr.squawk),
+          on_ground: changed(point.on_ground, curr.on_ground),
+          qnh: changed(point.qnh, curr.qnh),
         });
         curr = point;
       }
@@ -259,3 +1214,223 @@ impl TrackStore {
     Ok(compact)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::track::{codec::Codec, header::Header};
+
+  struct TempStore {
+    cfg: TrackConfig,
+  }
+
+  impl TempStore {
+    fn new(name: &str) -> Self {
+      let folder = std::env::temp_dir().join(format!("tracked-{}-{name}", std::process::id()));
+      let _ = std::fs::remove_dir_all(&folder);
+      create_dir_all(&folder).unwrap();
+      Self {
+        cfg: TrackConfig {
+          folder: folder.to_string_lossy().to_string(),
+          ..Default::default()
+        },
+      }
+    }
+
+    fn open(&self) -> TrackStore {
+      TrackStore::new(&self.cfg).unwrap()
+    }
+  }
+
+  impl Drop for TempStore {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.cfg.folder);
+    }
+  }
+
+  fn point(ts: i64) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat: 50.0 + ts as f64 * 1e-6,
+      lng: 8.0,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn track_ids_stay_in_the_store() {
+    for track_id in ["", "../x", "..", "a/b", "a\\b", "a\0b", "x/../../y"] {
+      assert!(
+        matches!(
+          check_track_id(track_id),
+          Err(TrackFileError::InvalidTrackId(_))
+        ),
+        "{track_id:?}"
+      );
+    }
+    for track_id in ["abc", "DLH4AB-20240101", "a.b", "."] {
+      assert!(check_track_id(track_id).is_ok(), "{track_id:?}");
+    }
+
+    let temp = TempStore::new("track-ids");
+    let store = temp.open();
+    let outcomes = store
+      .append_batch(&[("../../x", &point(0)), ("ok", &point(0))])
+      .unwrap();
+    assert!(matches!(outcomes[0], PointOutcome::Rejected(_)));
+    assert_eq!(outcomes[1], PointOutcome::Appended);
+    assert!(matches!(
+      store.remove_track("../../../etc/x"),
+      Err(TrackFileError::InvalidTrackId(_))
+    ));
+    assert!(matches!(
+      store.load_track_meta("a/b"),
+      Err(TrackFileError::InvalidTrackId(_))
+    ));
+    assert_eq!(store.get_metablock().unwrap().track_count, 1);
+  }
+
+  #[test]
+  fn replay_repairs_a_half_written_batch() {
+    let temp = TempStore::new("replay");
+    let store = temp.open();
+    let points: Vec<TrackPoint> = (0..10).map(|i| point(i * 1000)).collect();
+    let batch: Vec<(&str, &TrackPoint)> = points.iter().map(|p| ("a", p)).collect();
+    store.append_batch(&batch).unwrap();
+    let path = store.track_path("a").unwrap();
+    let meta = store.get_metablock().unwrap();
+    drop(store);
+
+    // crash after the block was written but before the header was
+    let size = std::fs::metadata(&path).unwrap().len();
+    let header = std::fs::read(&path).unwrap()[..Header::SIZE].to_vec();
+    let late = [point(10_000), point(11_000)];
+    let mut journal = Journal::open(Path::new(&temp.cfg.folder).join(".journal")).unwrap();
+    journal
+      .write(&JournalBatch {
+        meta,
+        tracks: vec![JournalTrack {
+          track_id: "a".into(),
+          count: Some(10),
+          size,
+        }],
+        points: late.iter().map(|p| (0, p.clone())).collect(),
+      })
+      .unwrap();
+    let mut tf = TrackFile::open(&path).unwrap();
+    tf.append_many(&late).unwrap();
+    drop(tf);
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    std::os::unix::fs::FileExt::write_all_at(&file, &header, 0).unwrap();
+    assert!(TrackFile::open(&path).is_err());
+
+    let store = temp.open();
+    let tf = TrackFile::open(&path).unwrap();
+    let stored: Vec<i64> = tf.read_all().unwrap().iter().map(|p| p.ts).collect();
+    let expected: Vec<i64> = (0..12).map(|i| i * 1000).collect();
+    assert_eq!(stored, expected);
+    let meta = store.get_metablock().unwrap();
+    assert_eq!(meta.track_count, 1);
+    assert_eq!(meta.point_count, 12);
+    assert_eq!(meta.total_bytes, tf.size());
+    assert!(journal.read().unwrap().is_none());
+  }
+
+  #[test]
+  fn expire_least_recently_updated() {
+    let temp = TempStore::new("expire");
+    let store = temp.open();
+    for track_id in ["a", "b", "c"] {
+      store.append_batch(&[(track_id, &point(0))]).unwrap();
+      std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+    // a track unknown to the index is left alone
+    create_dir_all(store.target_directory("x").unwrap()).unwrap();
+    std::fs::write(store.track_path("x").unwrap(), b"junk").unwrap();
+    let size = std::fs::metadata(store.track_path("c").unwrap())
+      .unwrap()
+      .len();
+
+    let report = store
+      .expire_tracks(&RetentionConfig {
+        max_size: Some(size),
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(report.removed_tracks, 2);
+    assert_eq!(report.removed_points, 2);
+    assert_eq!(report.removed_bytes, 2 * size);
+    assert!(!store.track_path("a").unwrap().exists());
+    assert!(!store.track_path("b").unwrap().exists());
+    assert!(store.track_path("c").unwrap().exists());
+    assert!(store.track_path("x").unwrap().exists());
+    let meta = store.get_metablock().unwrap();
+    assert_eq!((meta.track_count, meta.point_count), (1, 1));
+    assert_eq!(meta.total_bytes, size);
+
+    // counters which drifted below what is removed stay at zero
+    store.set_metablock(&MetaBlock::default());
+    let report = store
+      .expire_tracks(&RetentionConfig {
+        max_age: Some(0),
+        max_size: Some(0),
+        ..Default::default()
+      })
+      .unwrap();
+    assert_eq!(report.removed_tracks, 1);
+    let meta = store.get_metablock().unwrap();
+    assert_eq!(
+      (meta.track_count, meta.point_count, meta.total_bytes),
+      (0, 0, 0)
+    );
+  }
+
+  #[test]
+  fn reconcile_recounts_tracks() {
+    let temp = TempStore::new("reconcile");
+    let store = temp.open();
+    let points: Vec<TrackPoint> = (0..3).map(|i| point(i * 1000)).collect();
+    let batch: Vec<(&str, &TrackPoint)> = points.iter().map(|p| ("a", p)).collect();
+    store.append_batch(&batch).unwrap();
+    store.append_batch(&[("b", &point(0))]).unwrap();
+    create_dir_all(store.target_directory("x").unwrap()).unwrap();
+    std::fs::write(store.track_path("x").unwrap(), b"junk").unwrap();
+    let size = |track_id: &str| {
+      std::fs::metadata(store.track_path(track_id).unwrap())
+        .unwrap()
+        .len()
+    };
+    let total_bytes = size("a") + size("b");
+
+    let drifted = MetaBlock {
+      track_count: 10,
+      point_count: 100,
+      ..Default::default()
+    };
+    store.set_metablock(&drifted);
+    // writers aren't blocked by the scan and are counted exactly once
+    let report = std::thread::scope(|scope| {
+      let writer = scope.spawn(|| {
+        for i in 0..50 {
+          let track_id = format!("w{i}");
+          store.append_batch(&[(&track_id, &point(0))]).unwrap();
+        }
+      });
+      let report = store.reconcile().unwrap();
+      writer.join().unwrap();
+      report
+    });
+    assert_eq!(report.previous.track_count, 10);
+    assert_eq!(report.inspection.corrupt.len(), 1);
+
+    let written: u64 = (0..50).map(|i| size(&format!("w{i}"))).sum();
+    let meta = store.get_metablock().unwrap();
+    assert_eq!((meta.track_count, meta.point_count), (52, 54));
+    assert_eq!(meta.total_bytes, total_bytes + written);
+    assert_eq!(meta.corrupt_count, 1);
+    drop(store);
+
+    let meta = temp.open().get_metablock().unwrap();
+    assert_eq!((meta.track_count, meta.point_count), (52, 54));
+  }
+}
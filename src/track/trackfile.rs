@@ -1,41 +1,111 @@
 use std::{
-  fmt::Display,
   fs::{File, OpenOptions},
-  io::{Seek, SeekFrom, Write},
-  mem::size_of,
+  io::Write,
+  ops::Range,
   os::unix::fs::FileExt,
   path::{Path, PathBuf},
-  ptr::slice_from_raw_parts,
 };
 
-use super::{entry::TrackPoint, error::TrackFileError, header::Header};
+use serde::Serialize;
 
-#[allow(clippy::size_of_in_element_count)]
-fn to_raw<T: Sized>(obj: &T) -> Vec<u8> {
-  let slice = slice_from_raw_parts(obj, size_of::<T>()) as *const [u8];
-  let slice = unsafe { &*slice };
-  slice.into()
+use crate::config::DedupConfig;
+
+use super::{
+  block::{
+    decode_block, decode_block_header, encode_block, quantize, BlockInfo, BLOCK_HEADER_SIZE,
+    BLOCK_POINTS,
+  },
+  codec::{validate_point, Codec},
+  entry::TrackPoint,
+  error::TrackFileError,
+  header::{Header, FLAG_CHECKSUMS, VERSION_BLOCKS, VERSION_FLAT},
+  replace::replace_file,
+  trackmeta::{merge_meta, meta_path, read_meta, write_meta, TrackMeta},
+};
+
+/// Result of `TrackFile::repair`
+#[derive(Debug)]
+pub struct RepairReport {
+  /// point count stored in the header before the repair
+  pub header_count: u64,
+  /// number of points recovered
+  pub count: u64,
+  /// number of bytes the file has shrunk by
+  pub truncated_bytes: u64,
+}
+
+/// What happened to a point passed to `TrackFile::append_many`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "result", content = "reason", rename_all = "snake_case")]
+pub enum PointOutcome {
+  Appended,
+  /// a late point inserted before the latest one
+  Reordered,
+  /// the point extended a plateau of equal points, overwriting its end
+  Deduplicated,
+  /// the point is older than the reorder window allows
+  Dropped,
+  /// the point is invalid
+  Rejected(String),
+  /// the point couldn't be written, it may be sent again
+  Failed(String),
 }
 
-fn from_raw<T: Sized + Clone, I: AsRef<str> + Display>(
-  data: &[u8],
-  ident: I,
-) -> std::result::Result<T, TrackFileError> {
-  if data.len() < size_of::<T>() {
-    Err(TrackFileError::InsufficientDataLength(
-      ident.to_string(),
-      data.len(),
-    ))
-  } else {
-    let slice = data as *const [u8] as *const T;
-    let tp = unsafe { &*slice };
-    Ok(tp.clone())
+impl PointOutcome {
+  /// Whether the point is stored
+  pub fn is_accepted(&self) -> bool {
+    matches!(
+      self,
+      PointOutcome::Appended | PointOutcome::Reordered | PointOutcome::Deduplicated
+    )
   }
 }
 
+/// Result of `TrackFile::append_many`
+#[derive(Debug, Default)]
+pub struct AppendReport {
+  /// number of points the track has grown by
+  pub grown: usize,
+  /// outcomes in the order of the points passed
+  pub outcomes: Vec<PointOutcome>,
+}
+
+impl AppendReport {
+  pub fn count(&self, outcome: &PointOutcome) -> usize {
+    self.outcomes.iter().filter(|o| *o == outcome).count()
+  }
+}
+
+enum Layout {
+  /// Version 1: fixed-size raw records following the header, upgraded to
+  /// blocks once a point with extended fields is appended
+  Flat,
+  /// Version 2: delta-encoded blocks of up to BLOCK_POINTS points, only the
+  /// live ones are listed
+  Blocks(Vec<BlockInfo>),
+}
+
 pub struct TrackFile {
   file: File,
   path: PathBuf,
+  layout: Layout,
+  header: Header,
+  reorder_window: i64,
+  dedup: DedupConfig,
+}
+
+// adds a block read from a file to the ones preceding it, a block starting
+// before their end supersedes the blocks from its start on. Returns false if
+// the block doesn't start where another one does or the previous ones end.
+fn push_block(blocks: &mut Vec<BlockInfo>, info: BlockInfo) -> bool {
+  let end = blocks.last().map(|b| b.end_idx()).unwrap_or(0);
+  let pos = blocks.partition_point(|b| b.start < info.start);
+  if info.start != end && blocks.get(pos).is_none_or(|b| b.start != info.start) {
+    return false;
+  }
+  blocks.truncate(pos);
+  blocks.push(info);
+  true
 }
 
 impl TrackFile {
@@ -53,15 +123,20 @@ impl TrackFile {
   pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, TrackFileError> {
     let mut file = OpenOptions::new()
       .create(true)
+      .truncate(true)
       .write(true)
       .read(true)
       .open(&path)?;
     let header = Header::new()?;
-    let raw_header = to_raw(&header);
+    let raw_header = header.encode();
     file.write_all(&raw_header)?;
     Ok(Self {
       file,
       path: path.as_ref().to_path_buf(),
+      layout: Layout::Blocks(vec![]),
+      header,
+      reorder_window: 0,
+      dedup: DedupConfig::default(),
     })
   }
 
@@ -70,7 +145,15 @@ impl TrackFile {
     let res = OpenOptions::new().write(true).read(true).open(&path);
     match res {
       Ok(file) => {
-        let tf = Self { file, path };
+        let header = Self::load_header(&file)?;
+        let mut tf = Self {
+          file,
+          path,
+          layout: Layout::Flat,
+          header,
+          reorder_window: 0,
+          dedup: DedupConfig::default(),
+        };
         tf.check()?;
         Ok(tf)
       }
@@ -84,27 +167,180 @@ impl TrackFile {
     }
   }
 
-  fn check(&self) -> Result<(), TrackFileError> {
-    let header = self.read_file_header()?;
+  fn check(&mut self) -> Result<(), TrackFileError> {
+    let header = self.header.clone();
     if !header.check_magic() {
-      Err(TrackFileError::InvalidMagicNumber)
-    } else {
-      let meta = std::fs::metadata(&self.path)?;
-      let expected_len = (header.count() as usize) * Self::entry_size() + Self::header_size();
-      let real_len = meta.len() as usize;
-      if real_len != expected_len {
-        Err(TrackFileError::InvalidFileLength(expected_len, real_len))
-      } else {
+      return Err(TrackFileError::InvalidMagicNumber);
+    }
+
+    let meta = std::fs::metadata(&self.path)?;
+    let real_len = meta.len() as usize;
+    match header.version() {
+      VERSION_FLAT => {
+        let expected_len = (header.count() as usize) * Self::entry_size() + Self::header_size();
+        if real_len != expected_len {
+          Err(TrackFileError::InvalidFileLength(expected_len, real_len))
+        } else {
+          self.layout = Layout::Flat;
+          Ok(())
+        }
+      }
+      VERSION_BLOCKS => {
+        let blocks = self.scan_blocks(&header, real_len)?;
+        self.layout = Layout::Blocks(blocks);
         Ok(())
       }
+      version => Err(TrackFileError::UnsupportedVersion(version.into())),
     }
   }
 
-  fn inc(&mut self) -> Result<(), TrackFileError> {
-    let mut header = self.read_file_header()?;
-    header.inc();
-    self.write_file_header(&header)?;
-    Ok(())
+  // Blocks are read in file order. A block starting before the end of the
+  // preceding ones supersedes the blocks from its start on, see
+  // `write_blocks`.
+  fn scan_blocks(
+    &self,
+    header: &Header,
+    real_len: usize,
+  ) -> Result<Vec<BlockInfo>, TrackFileError> {
+    let mut blocks = vec![];
+    let mut buf = vec![0; BLOCK_HEADER_SIZE];
+    let mut offset = Self::header_size();
+
+    while offset < real_len {
+      if offset + BLOCK_HEADER_SIZE > real_len {
+        return Err(TrackFileError::InvalidFileLength(
+          offset + BLOCK_HEADER_SIZE,
+          real_len,
+        ));
+      }
+      self.file.read_exact_at(&mut buf, offset as u64)?;
+      let info = decode_block_header(&buf, offset as u64)?;
+      let end = info.end() as usize;
+      if end > real_len {
+        return Err(TrackFileError::InvalidFileLength(end, real_len));
+      }
+      if !push_block(&mut blocks, info) {
+        return Err(TrackFileError::InvalidBlock(offset as u64));
+      }
+      offset = end;
+    }
+
+    let count = blocks.last().map(|b| b.end_idx()).unwrap_or(0);
+    if count as u64 != header.count() {
+      return Err(TrackFileError::CountMismatch(header.count(), count as u64));
+    }
+    // blocks are only ever appended, so a torn write can only damage the last one
+    if let Some(last) = blocks.last() {
+      match self.read_block(last) {
+        Err(TrackFileError::IOError(err)) => return Err(err.into()),
+        Err(_) => return Err(TrackFileError::InvalidBlock(last.offset)),
+        Ok(_) => (),
+      }
+    }
+    Ok(blocks)
+  }
+
+  /// Reconciles the header point count with the data actually stored in
+  /// the file: partially written records or blocks are truncated, the count
+  /// is recomputed from the complete ones and trailing points breaking the
+  /// ts order are dropped. Block files are written anew with the recovered
+  /// points.
+  pub fn repair<P: AsRef<Path>>(path: P) -> Result<RepairReport, TrackFileError> {
+    let path = path.as_ref().to_path_buf();
+    let file = OpenOptions::new().write(true).read(true).open(&path)?;
+    let mut header = Self::load_header(&file)?;
+    let mut tf = Self {
+      file,
+      path,
+      layout: Layout::Flat,
+      header: header.clone(),
+      reorder_window: 0,
+      dedup: DedupConfig::default(),
+    };
+
+    if !header.check_magic() {
+      return Err(TrackFileError::InvalidMagicNumber);
+    }
+
+    let header_count = header.count();
+    let real_len = tf.file.metadata()?.len();
+    let count = match header.version() {
+      VERSION_FLAT => {
+        let (count, len) = tf.repair_flat(real_len as usize)?;
+        tf.file.set_len(len as u64)?;
+        header.count = count as u64;
+        tf.write_file_header(&header)?;
+        tf.file.sync_all()?;
+        count
+      }
+      VERSION_BLOCKS => {
+        let points = tf.repair_blocks(real_len as usize)?;
+        tf.rewrite(&points)?;
+        points.len()
+      }
+      version => return Err(TrackFileError::UnsupportedVersion(version.into())),
+    };
+
+    Ok(RepairReport {
+      header_count,
+      count: count as u64,
+      truncated_bytes: real_len.saturating_sub(tf.size()),
+    })
+  }
+
+  // returns the number of valid points and the length of the file they take
+  fn repair_flat(&self, real_len: usize) -> Result<(usize, usize), TrackFileError> {
+    let full = real_len.saturating_sub(Self::header_size()) / Self::entry_size();
+    let mut buf = Self::make_entry_buf();
+    let mut last_ts = i64::MIN;
+    let mut count = 0;
+
+    for idx in 0..full {
+      let offset = Self::header_size() + idx * Self::entry_size();
+      self.file.read_exact_at(&mut buf, offset as u64)?;
+      match TrackPoint::decode(&buf) {
+        Ok(tp) if tp.ts >= last_ts => last_ts = tp.ts,
+        _ => break,
+      }
+      count += 1;
+    }
+
+    Ok((count, Self::header_size() + count * Self::entry_size()))
+  }
+
+  // returns the sorted points of the blocks preceding the first damaged one
+  fn repair_blocks(&self, real_len: usize) -> Result<Vec<TrackPoint>, TrackFileError> {
+    let mut buf = vec![0; BLOCK_HEADER_SIZE];
+    let mut offset = Self::header_size();
+    let mut blocks = vec![];
+
+    while offset + BLOCK_HEADER_SIZE <= real_len {
+      self.file.read_exact_at(&mut buf, offset as u64)?;
+      let info = match decode_block_header(&buf, offset as u64) {
+        Ok(info) if info.end() as usize <= real_len => info,
+        _ => break,
+      };
+      match self.read_block(&info) {
+        Ok(_) => (),
+        Err(TrackFileError::IOError(err)) => return Err(err.into()),
+        Err(_) => break,
+      }
+      offset = info.end() as usize;
+      if !push_block(&mut blocks, info) {
+        break;
+      }
+    }
+
+    let mut points = vec![];
+    for info in blocks.iter() {
+      points.extend(self.read_block(info)?);
+    }
+    let sorted = points
+      .windows(2)
+      .position(|pair| pair[1].ts < pair[0].ts)
+      .map_or(points.len(), |idx| idx + 1);
+    points.truncate(sorted);
+    Ok(points)
   }
 
   fn make_entry_buf() -> Vec<u8> {
@@ -118,64 +354,185 @@ impl TrackFile {
   }
 
   const fn entry_size() -> usize {
-    size_of::<TrackPoint>()
+    TrackPoint::SIZE
   }
 
   const fn header_size() -> usize {
-    size_of::<Header>()
+    Header::SIZE
   }
 
-  fn read_file_header(&self) -> Result<Header, TrackFileError> {
+  fn load_header(file: &File) -> Result<Header, TrackFileError> {
     let mut buf = Self::make_header_buf();
-    self.file.read_at(&mut buf, 0)?;
-    from_raw(&buf, "header")
+    file.read_at(&mut buf, 0)?;
+    Ok(Header::decode(&buf)?)
+  }
+
+  fn read_file_header(&self) -> Result<Header, TrackFileError> {
+    Self::load_header(&self.file)
   }
 
   fn write_file_header(&mut self, header: &Header) -> Result<(), TrackFileError> {
-    let buf = to_raw(header);
+    let buf = header.encode();
     self.file.write_at(&buf, 0)?;
+    self.header = header.clone();
     Ok(())
   }
 
+  fn read_block(&self, info: &BlockInfo) -> Result<Vec<TrackPoint>, TrackFileError> {
+    let mut buf = vec![0; BLOCK_HEADER_SIZE + info.len];
+    self.file.read_exact_at(&mut buf, info.offset)?;
+    decode_block(info, &buf)
+  }
+
   pub fn count(&self) -> Result<u64, TrackFileError> {
-    let header = self.read_file_header()?;
-    Ok(header.count())
+    Ok(self.header.count())
+  }
+
+  /// Timestamps of the first and the last point
+  pub fn ts_range(&self) -> Result<Option<(i64, i64)>, TrackFileError> {
+    let count = self.count()? as usize;
+    if count == 0 {
+      return Ok(None);
+    }
+    match &self.layout {
+      Layout::Flat => Ok(Some((self.read_ts_at(0)?, self.read_ts_at(count - 1)?))),
+      Layout::Blocks(blocks) => Ok(Some((
+        blocks.first().unwrap().first_ts,
+        blocks.last().unwrap().last_ts,
+      ))),
+    }
+  }
+
+  /// Size of the track file in bytes
+  pub fn size(&self) -> u64 {
+    match &self.layout {
+      Layout::Flat => {
+        (Self::header_size() + self.header.count() as usize * Self::entry_size()) as u64
+      }
+      Layout::Blocks(blocks) => blocks
+        .last()
+        .map(|b| b.end())
+        .unwrap_or(Self::header_size() as u64),
+    }
+  }
+
+  pub fn last(&self) -> Result<Option<TrackPoint>, TrackFileError> {
+    let count = self.count()? as usize;
+    if count == 0 {
+      Ok(None)
+    } else {
+      self.read_at(count - 1).map(Some)
+    }
   }
 
-  pub fn destroy(self) -> Result<(), TrackFileError> {
-    std::fs::remove_file(self.path)?;
+  /// Flushes written data to disk
+  pub fn sync(&self) -> Result<(), TrackFileError> {
+    self.file.sync_data()?;
     Ok(())
   }
 
+  /// Removes the track file along with its metadata, the handle must not
+  /// be used afterwards
+  pub fn destroy(&self) -> Result<(), TrackFileError> {
+    std::fs::remove_file(&self.path)?;
+    match std::fs::remove_file(meta_path(&self.path)) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+      _ => Ok(()),
+    }
+  }
+
+  pub fn read_meta(&self) -> Result<TrackMeta, TrackFileError> {
+    read_meta(&meta_path(&self.path))
+  }
+
+  /// Merges `update` into the track metadata and returns the result
+  pub fn update_meta(&mut self, update: &TrackMeta) -> Result<TrackMeta, TrackFileError> {
+    let path = meta_path(&self.path);
+    let mut meta = read_meta(&path)?;
+    merge_meta(&mut meta, update);
+    write_meta(&path, &meta)?;
+    Ok(meta)
+  }
+
   pub fn get_header(&self) -> Result<Header, TrackFileError> {
-    // todo caching?
-    self.read_file_header()
+    Ok(self.header.clone())
   }
 
   pub fn read_all(&self) -> Result<Vec<TrackPoint>, TrackFileError> {
-    let header = self.read_file_header()?;
+    let header = &self.header;
 
-    let mut buf = Self::make_entry_buf();
     let mut res = vec![];
-    for idx in 0..header.count() {
-      let idx = idx as usize;
-      let offset = Self::header_size() + idx * Self::entry_size();
-      self.file.read_at(&mut buf, offset as u64)?;
-      let tp = from_raw(&buf, "track entry")?;
-      res.push(tp);
+    match &self.layout {
+      Layout::Flat => {
+        let mut buf = Self::make_entry_buf();
+        for idx in 0..header.count() {
+          let idx = idx as usize;
+          let offset = Self::header_size() + idx * Self::entry_size();
+          self.file.read_at(&mut buf, offset as u64)?;
+          let tp = TrackPoint::decode(&buf)?;
+          res.push(tp);
+        }
+      }
+      Layout::Blocks(blocks) => {
+        for info in blocks.iter() {
+          res.extend(self.read_block(info)?);
+        }
+      }
     }
     Ok(res)
   }
 
-  pub fn read_at(&self, pos: usize) -> Result<TrackPoint, TrackFileError> {
+  /// Reads and checks every point of the track returning the index of
+  /// the first damaged one. Version 1 files have no checksums and can only
+  /// be checked for undecodable, unsorted or out-of-range points.
+  pub fn verify(&self) -> Result<Option<usize>, TrackFileError> {
+    // header checksum is verified on decoding
     let header = self.read_file_header()?;
+    let mut last_ts = i64::MIN;
+
+    match &self.layout {
+      Layout::Flat => {
+        let mut buf = Self::make_entry_buf();
+        for idx in 0..header.count() as usize {
+          let offset = Self::header_size() + idx * Self::entry_size();
+          self.file.read_exact_at(&mut buf, offset as u64)?;
+          match TrackPoint::decode(&buf) {
+            Ok(tp) if tp.ts >= last_ts && validate_point(&tp).is_ok() => last_ts = tp.ts,
+            _ => return Ok(Some(idx)),
+          }
+        }
+      }
+      Layout::Blocks(blocks) => {
+        for info in blocks.iter() {
+          let points = match self.read_block(info) {
+            Ok(points) => points,
+            Err(TrackFileError::IOError(err)) => return Err(err.into()),
+            Err(_) => return Ok(Some(info.start)),
+          };
+          for (idx, tp) in points.iter().enumerate() {
+            if tp.ts < last_ts || validate_point(tp).is_err() {
+              return Ok(Some(info.start + idx));
+            }
+            last_ts = tp.ts;
+          }
+        }
+      }
+    }
+    Ok(None)
+  }
+
+  pub fn read_at(&self, pos: usize) -> Result<TrackPoint, TrackFileError> {
+    let header = &self.header;
     if pos as u64 >= header.count() {
       Err(TrackFileError::IndexError(pos))
+    } else if let Layout::Blocks(_) = self.layout {
+      let mut entries = self.read_multiple_at(pos, 1)?;
+      entries.pop().ok_or(TrackFileError::IndexError(pos))
     } else {
       let mut buf = Self::make_entry_buf();
       let offset = Self::header_size() + pos * Self::entry_size();
       self.file.read_at(&mut buf, offset as u64)?;
-      let e = from_raw(&buf, "track entry")?;
+      let e = TrackPoint::decode(&buf)?;
       Ok(e)
     }
   }
@@ -185,8 +542,7 @@ impl TrackFile {
     pos: usize,
     len: usize,
   ) -> Result<Vec<TrackPoint>, TrackFileError> {
-    let header = self.read_file_header()?;
-    let count = header.count() as usize;
+    let count = self.header.count() as usize;
     let mut len = len;
 
     if pos + len > count {
@@ -197,6 +553,19 @@ impl TrackFile {
       return Ok(Vec::new());
     }
 
+    if let Layout::Blocks(blocks) = &self.layout {
+      let end = pos + len;
+      let first = blocks.partition_point(|b| b.end_idx() <= pos);
+      let mut entries = vec![];
+      for info in blocks[first..].iter().take_while(|b| b.start < end) {
+        let points = self.read_block(info)?;
+        let from = pos.saturating_sub(info.start);
+        let to = (end - info.start).min(info.count);
+        entries.extend_from_slice(&points[from..to]);
+      }
+      return Ok(entries);
+    }
+
     let mut buf = vec![];
     let entry_len = Self::entry_size();
     buf.resize(len * entry_len, 0);
@@ -208,49 +577,752 @@ impl TrackFile {
     for idx in 0..len {
       let start = idx * entry_len;
       let end = (idx + 1) * entry_len;
-      let e = from_raw(&buf[start..end], "track entry")?;
+      let e = TrackPoint::decode(&buf[start..end])?;
       entries.push(e);
     }
 
     Ok(entries)
   }
 
-  pub fn append(&mut self, entry: &TrackPoint) -> Result<bool, TrackFileError> {
-    let header = self.get_header()?;
-    let count = header.count() as usize;
-    let mut overwrite = false;
-    let offset = if count < 2 {
-      // if less than 2 points exist, append only
-      0
+  fn read_ts_at(&self, pos: usize) -> Result<i64, TrackFileError> {
+    let mut buf = Self::make_entry_buf();
+    let offset = Self::header_size() + pos * Self::entry_size();
+    self.file.read_at(&mut buf, offset as u64)?;
+    let e = TrackPoint::decode(&buf)?;
+    Ok(e.ts)
+  }
+
+  // returns the index of the first point for which pred(ts) is false,
+  // points are guaranteed to be sorted by ts by append()
+  fn partition_point<F>(&self, count: usize, pred: F) -> Result<usize, TrackFileError>
+  where
+    F: Fn(i64) -> bool,
+  {
+    if let Layout::Blocks(blocks) = &self.layout {
+      // every block before the found one consists of matching points only
+      let idx = blocks.partition_point(|b| pred(b.last_ts));
+      return match blocks.get(idx) {
+        None => Ok(count),
+        Some(info) if !pred(info.first_ts) => Ok(info.start),
+        Some(info) => {
+          let points = self.read_block(info)?;
+          Ok(info.start + points.partition_point(|p| pred(p.ts)))
+        }
+      };
+    }
+
+    let mut lo = 0;
+    let mut hi = count;
+    while lo < hi {
+      let mid = lo + (hi - lo) / 2;
+      if pred(self.read_ts_at(mid)?) {
+        lo = mid + 1;
+      } else {
+        hi = mid;
+      }
+    }
+    Ok(lo)
+  }
+
+  /// Finds the range of indices of points with `after < ts < before`
+  /// without reading the entire file
+  pub fn find_range(
+    &self,
+    after: Option<i64>,
+    before: Option<i64>,
+  ) -> Result<Range<usize>, TrackFileError> {
+    let count = self.count()? as usize;
+    let start = match after {
+      Some(after) => self.partition_point(count, |ts| ts <= after)?,
+      None => 0,
+    };
+    let end = match before {
+      Some(before) => self.partition_point(count, |ts| ts < before)?,
+      None => count,
+    };
+    Ok(start..end.max(start))
+  }
+
+  pub fn read_range(
+    &self,
+    after: Option<i64>,
+    before: Option<i64>,
+  ) -> Result<Vec<TrackPoint>, TrackFileError> {
+    let range = self.find_range(after, before)?;
+    self.read_multiple_at(range.start, range.len())
+  }
+
+  /// Drops all points with a ts greater than `ts`, returns the number of
+  /// points removed
+  pub fn truncate_after(&mut self, ts: i64) -> Result<u64, TrackFileError> {
+    let count = self.count()? as usize;
+    let idx = self.partition_point(count, |t| t <= ts)?;
+    if idx == count {
+      return Ok(0);
+    }
+
+    match self.layout {
+      Layout::Flat => {
+        let len = Self::header_size() + idx * Self::entry_size();
+        self.file.set_len(len as u64)?;
+      }
+      Layout::Blocks(_) => self.write_blocks(&[], count - idx)?,
+    }
+
+    let mut header = self.header.clone();
+    header.set_count(idx as u64);
+    self.write_file_header(&header)?;
+    self.compact_if_wasteful()?;
+    Ok((count - idx) as u64)
+  }
+
+  /// Replaces the tail of a block track file: `replace` trailing points are
+  /// dropped and `entries` are appended. Written blocks are never modified:
+  /// the block holding the first replaced point, or the last one if it isn't
+  /// full yet, is written again with the new points at the end of the file
+  /// and supersedes the blocks from its start on. The header is updated by
+  /// the caller afterwards.
+  fn write_blocks(&mut self, entries: &[TrackPoint], replace: usize) -> Result<(), TrackFileError> {
+    let Layout::Blocks(blocks) = &self.layout else {
+      unreachable!("write_blocks called on a flat track file")
+    };
+
+    let keep = blocks.last().map(|b| b.end_idx()).unwrap_or(0) - replace;
+    let mut first = blocks.partition_point(|b| b.end_idx() <= keep);
+    if first == blocks.len() && blocks.last().is_some_and(|b| b.count < BLOCK_POINTS) {
+      first -= 1;
+    }
+    let (start, mut points) = match blocks.get(first) {
+      Some(info) => (info.start, self.read_block(info)?),
+      None => (keep, vec![]),
+    };
+    points.truncate(keep - start);
+    points.extend_from_slice(entries);
+
+    let mut infos = vec![];
+    if points.is_empty() {
+      // whole blocks are dropped: the file past the last kept block holds
+      // only blocks being dropped or superseded ones
+      let len = match first.checked_sub(1) {
+        Some(last) => blocks[last].end(),
+        None => Self::header_size() as u64,
+      };
+      self.file.set_len(len)?;
     } else {
-      let mut last_two = self.read_multiple_at(count - 2, 2)?;
-      let last = last_two.pop().unwrap();
-      let prev = last_two.pop().unwrap();
-      if last == prev && prev == *entry {
+      let offset = self.size();
+      let mut data = vec![];
+      let mut block_start = start;
+      for chunk in points.chunks(BLOCK_POINTS) {
+        let block = encode_block(chunk, block_start);
+        let info = decode_block_header(&block, offset + data.len() as u64)?;
+        block_start = info.end_idx();
+        data.extend_from_slice(&block);
+        infos.push(info);
+      }
+      self.file.write_all_at(&data, offset)?;
+    }
+
+    if let Layout::Blocks(blocks) = &mut self.layout {
+      blocks.truncate(first);
+      blocks.extend(infos);
+    }
+    Ok(())
+  }
+
+  // rewrites a block track file once superseded blocks take more space than
+  // the live ones
+  fn compact_if_wasteful(&mut self) -> Result<(), TrackFileError> {
+    let Layout::Blocks(blocks) = &self.layout else {
+      return Ok(());
+    };
+    let live = blocks
+      .iter()
+      .map(|b| (BLOCK_HEADER_SIZE + b.len) as u64)
+      .sum::<u64>()
+      + Self::header_size() as u64;
+    if self.size() - live > live {
+      let points = self.read_all()?;
+      self.rewrite(&points)?;
+    }
+    Ok(())
+  }
+
+  // atomically replaces the file with a block track file holding `points`
+  fn rewrite(&mut self, points: &[TrackPoint]) -> Result<(), TrackFileError> {
+    let header = Header {
+      version: VERSION_BLOCKS,
+      flags: FLAG_CHECKSUMS,
+      count: points.len() as u64,
+      ..self.header.clone()
+    };
+    let mut data = header.encode();
+    let mut blocks = vec![];
+    for (idx, chunk) in points.chunks(BLOCK_POINTS).enumerate() {
+      let block = encode_block(chunk, idx * BLOCK_POINTS);
+      blocks.push(decode_block_header(&block, data.len() as u64)?);
+      data.extend_from_slice(&block);
+    }
+
+    self.file = replace_file(&self.path, &data)?;
+    self.header = header;
+    self.layout = Layout::Blocks(blocks);
+    Ok(())
+  }
+
+  // whether `entry` can overwrite `last` as the end of the plateau started
+  // by `first`. The points are compared to the first one so that a slow
+  // drift within the tolerance doesn't extend the plateau forever.
+  fn extends_plateau(&self, first: &TrackPoint, last: &TrackPoint, entry: &TrackPoint) -> bool {
+    let ms = |secs: Option<u64>| {
+      secs.map_or(i64::MAX, |secs| {
+        secs.saturating_mul(1000).min(i64::MAX as u64) as i64
+      })
+    };
+    let heartbeat = ms(self.dedup.heartbeat);
+    let max_gap = ms(self.dedup.max_gap);
+    last.is_near(first, &self.dedup)
+      && entry.is_near(first, &self.dedup)
+      && entry.ts.saturating_sub(first.ts) <= heartbeat
+      && entry.ts.saturating_sub(last.ts) <= max_gap
+  }
+
+  /// Sets the differences of points taken as equal by the deduplication
+  pub fn set_dedup_tolerance(&mut self, tolerance: DedupConfig) {
+    self.dedup = tolerance;
+  }
+
+  /// Sets how late in ms a point may arrive to be inserted in its sorted
+  /// position instead of being dropped
+  pub fn set_reorder_window(&mut self, window: u64) {
+    self.reorder_window = window.min(i64::MAX as u64) as i64;
+  }
+
+  /// Appends points with a single write of the records and a single header
+  /// update. Points are deduplicated: if the last two points are equal and
+  /// the new one equals them too, the last one is replaced.
+  ///
+  /// Points older than the latest one are inserted in their sorted position,
+  /// rewriting the tail of the file, if they are within the reorder window,
+  /// and dropped otherwise. Points with the ts of a stored one are taken as
+  /// re-delivered and deduplicated. Invalid points are skipped, the outcome of every
+  /// point is reported.
+  pub fn append_many(&mut self, entries: &[TrackPoint]) -> Result<AppendReport, TrackFileError> {
+    // version 1 files can't store the extended fields, rather than dropping
+    // them the track is upgraded to blocks
+    if matches!(self.layout, Layout::Flat) && entries.iter().any(TrackPoint::has_extended) {
+      let points = self.read_all()?;
+      self.rewrite(&points)?;
+    }
+    let count = self.count()? as usize;
+    let mut outcomes = vec![PointOutcome::Appended; entries.len()];
+    let last_ts = self.last()?.map(|p| p.ts).unwrap_or(i64::MIN);
+    let mut latest = last_ts;
+
+    let mut accepted = vec![];
+    for (idx, entry) in entries.iter().enumerate() {
+      if let Err(err) = validate_point(entry) {
+        outcomes[idx] = PointOutcome::Rejected(format!("{err}"));
+        continue;
+      }
+      if entry.ts < latest {
+        if entry.ts < latest.saturating_sub(self.reorder_window) {
+          outcomes[idx] = PointOutcome::Dropped;
+          continue;
+        }
+        outcomes[idx] = PointOutcome::Reordered;
+      }
+      latest = latest.max(entry.ts);
+      let entry = match self.layout {
+        Layout::Flat => entry.clone(),
+        Layout::Blocks(_) => quantize(entry),
+      };
+      accepted.push((idx, entry));
+    }
+    if accepted.is_empty() {
+      return Ok(AppendReport { grown: 0, outcomes });
+    }
+
+    // late points go after the existing ones with the same ts, the points
+    // from there on are merged with the new ones and written again
+    accepted.sort_by_key(|(_, p)| p.ts);
+    let first_ts = accepted[0].1.ts;
+    let merge_from = if first_ts >= last_ts {
+      count
+    } else {
+      self.partition_point(count, |ts| ts <= first_ts)?
+    };
+    // two more points are read for the deduplication
+    let tail_start = merge_from.saturating_sub(2);
+    let tail = self.read_multiple_at(tail_start, count - tail_start)?;
+    let (points, existing) = tail.split_at(merge_from - tail_start);
+    let mut points = points.to_vec();
+    // indices of the entries the points come from, None for existing ones
+    let mut origins: Vec<Option<usize>> = vec![None; points.len()];
+    let tail_len = count - tail_start;
+    let mut first_new = points.len();
+
+    let mut existing = existing.iter().peekable();
+    let mut accepted = accepted.into_iter().peekable();
+    loop {
+      let (origin, entry) = match (existing.peek(), accepted.peek()) {
+        (Some(old), Some((_, new))) if old.ts <= new.ts => {
+          (None, existing.next().cloned().unwrap())
+        }
+        (Some(_), None) => (None, existing.next().cloned().unwrap()),
+        (_, Some(_)) => {
+          let (idx, entry) = accepted.next().unwrap();
+          (Some(idx), entry)
+        }
+        (None, None) => break,
+      };
+      // a re-delivered point is stored once, the ts of a point identifies it
+      if let Some(idx) = origin {
+        if points.last().is_some_and(|last| last.ts == entry.ts) {
+          outcomes[idx] = PointOutcome::Deduplicated;
+          continue;
+        }
+      }
+      // existing points are never deduplicated again, even if the tolerances
+      // have changed since they were written, so a merge can't shrink the track
+      let len = points.len();
+      if origin.is_some()
+        && len > 1
+        && self.extends_plateau(&points[len - 2], &points[len - 1], &entry)
+      {
         // if the last two points are equal and the new one equals to them
         // replace the last one, overwriting only timestamp
-        overwrite = true;
-        -(Self::entry_size() as i64)
-      } else {
-        // otherwise, append
-        0
+        points.pop();
+        if let Some(idx) = origins.pop().flatten() {
+          outcomes[idx] = PointOutcome::Deduplicated;
+        }
+        if let Some(idx) = origin {
+          outcomes[idx] = PointOutcome::Deduplicated;
+        }
+        first_new = first_new.min(points.len());
       }
-    };
+      points.push(entry);
+      origins.push(origin);
+    }
+
+    let replace = tail_len - first_new;
+    let new_points = &points[first_new..];
+    let new_count = count - replace + new_points.len();
 
-    if count > 0 {
-      let last = self.read_at(count - 1)?;
-      if last.ts > entry.ts {
-        return Err(TrackFileError::SequenceError(entry.ts));
+    match self.layout {
+      Layout::Blocks(_) => self.write_blocks(new_points, replace)?,
+      Layout::Flat => {
+        // records are written before the header so that an interrupted
+        // append leaves extra records which repair() can pick up
+        let mut data = Vec::with_capacity(new_points.len() * Self::entry_size());
+        for point in new_points.iter() {
+          point.encode_into(&mut data);
+        }
+        let offset = Self::header_size() + (count - replace) * Self::entry_size();
+        self.file.write_all_at(&data, offset as u64)?;
+        if new_count < count {
+          let len = Self::header_size() + new_count * Self::entry_size();
+          self.file.set_len(len as u64)?;
+        }
       }
     }
 
-    if offset == 0 {
-      self.inc()?;
+    let mut header = self.header.clone();
+    header.set_count(new_count as u64);
+    self.write_file_header(&header)?;
+    self.compact_if_wasteful()?;
+    Ok(AppendReport {
+      grown: new_count.saturating_sub(count),
+      outcomes,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tracked-{}-{name}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+  }
+
+  fn point(ts: i64) -> TrackPoint {
+    TrackPoint {
+      ts,
+      lat: 48.0 + ts as f64 * 1e-7,
+      lng: 11.0,
+      hdg: 90,
+      gs: 250,
+      alt: 10000,
+      ..Default::default()
+    }
+  }
+
+  fn still(ts: i64) -> TrackPoint {
+    TrackPoint { ts, ..point(0) }
+  }
+
+  fn timestamps(tf: &TrackFile) -> Vec<i64> {
+    tf.read_all().unwrap().iter().map(|p| p.ts).collect()
+  }
+
+  #[test]
+  fn append_and_reopen() {
+    let path = temp_path("append");
+    let mut tf = TrackFile::create(&path).unwrap();
+    let points: Vec<TrackPoint> = (0..300).map(|i| point(i * 1000)).collect();
+    for chunk in points.chunks(7) {
+      let report = tf.append_many(chunk).unwrap();
+      assert_eq!(report.grown, chunk.len());
     }
+    drop(tf);
+
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.count().unwrap(), 300);
+    assert_eq!(tf.ts_range().unwrap(), Some((0, 299_000)));
+    assert_eq!(tf.verify().unwrap(), None);
+    let stored = tf.read_all().unwrap();
+    for (stored, point) in stored.iter().zip(points.iter()) {
+      assert_eq!(stored.ts, point.ts);
+      assert_eq!(*stored, quantize(point));
+    }
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn late_points() {
+    let path = temp_path("late");
+    let mut tf = TrackFile::create(&path).unwrap();
+    tf.set_reorder_window(10_000);
+    let points: Vec<TrackPoint> = (0..10).map(|i| point(i * 1000)).collect();
+    tf.append_many(&points).unwrap();
+
+    let report = tf
+      .append_many(&[point(5500), point(-2000), point(8500), point(20_000)])
+      .unwrap();
+    assert_eq!(
+      report.outcomes,
+      [
+        PointOutcome::Reordered,
+        PointOutcome::Dropped,
+        PointOutcome::Reordered,
+        PointOutcome::Appended,
+      ]
+    );
+    assert_eq!(report.grown, 3);
+    let mut expected: Vec<i64> = (0..10).map(|i| i * 1000).collect();
+    expected.extend([5500, 8500, 20_000]);
+    expected.sort();
+    assert_eq!(timestamps(&tf), expected);
+    drop(tf);
+    assert_eq!(timestamps(&TrackFile::open(&path).unwrap()), expected);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn invalid_points_are_rejected() {
+    let path = temp_path("invalid");
+    let mut tf = TrackFile::create(&path).unwrap();
+    let invalid = TrackPoint {
+      lat: 200.0,
+      ..point(1000)
+    };
+    let report = tf
+      .append_many(&[point(0), invalid.clone(), point(2000)])
+      .unwrap();
+    assert!(matches!(report.outcomes[1], PointOutcome::Rejected(_)));
+    assert_eq!(report.grown, 2);
+    let report = tf.append_many(&[invalid]).unwrap();
+    assert!(matches!(report.outcomes[0], PointOutcome::Rejected(_)));
+    assert_eq!(report.grown, 0);
+    assert_eq!(timestamps(&tf), [0, 2000]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn redelivered_points_are_stored_once() {
+    let path = temp_path("redelivered");
+    let mut tf = TrackFile::create(&path).unwrap();
+    tf.set_reorder_window(10_000);
+    let points: Vec<TrackPoint> = (0..10).map(|i| point(i * 1000)).collect();
+    tf.append_many(&points).unwrap();
+
+    let moved = TrackPoint {
+      alt: 20000,
+      ..point(9000)
+    };
+    let report = tf
+      .append_many(&[point(5000), moved, point(10_000), point(10_000)])
+      .unwrap();
+    assert_eq!(
+      report.outcomes,
+      [
+        PointOutcome::Deduplicated,
+        PointOutcome::Deduplicated,
+        PointOutcome::Appended,
+        PointOutcome::Deduplicated,
+      ]
+    );
+    assert_eq!(report.grown, 1);
+    let expected: Vec<i64> = (0..11).map(|i| i * 1000).collect();
+    assert_eq!(timestamps(&tf), expected);
+    // the first delivery is kept
+    assert_eq!(tf.read_all().unwrap()[9].alt, 10000);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn plateau_keeps_its_ends() {
+    let path = temp_path("plateau");
+    let mut tf = TrackFile::create(&path).unwrap();
+    let report = tf
+      .append_many(&[still(0), still(1000), still(2000)])
+      .unwrap();
+    assert_eq!(
+      report.outcomes,
+      [
+        PointOutcome::Appended,
+        PointOutcome::Deduplicated,
+        PointOutcome::Deduplicated,
+      ]
+    );
+    assert_eq!(report.grown, 2);
+    tf.append_many(&[still(3000)]).unwrap();
+    assert_eq!(timestamps(&tf), [0, 3000]);
+
+    // a point off the plateau starts moving again
+    tf.append_many(&[point(4000)]).unwrap();
+    assert_eq!(timestamps(&tf), [0, 3000, 4000]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn plateau_heartbeat_and_max_gap() {
+    let path = temp_path("heartbeat");
+    let mut tf = TrackFile::create(&path).unwrap();
+    tf.set_dedup_tolerance(DedupConfig {
+      heartbeat: Some(20),
+      max_gap: Some(30),
+      ..Default::default()
+    });
+    for ts in (0..=30).step_by(5) {
+      tf.append_many(&[still(ts * 1000)]).unwrap();
+    }
+    // a point is kept at least every heartbeat
+    assert_eq!(timestamps(&tf), [0, 20_000, 30_000]);
+
+    // a silence longer than max_gap stays visible
+    tf.append_many(&[still(70_000)]).unwrap();
+    tf.append_many(&[still(75_000)]).unwrap();
+    assert_eq!(timestamps(&tf), [0, 20_000, 30_000, 70_000, 75_000]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn merge_never_shrinks_the_track() {
+    let path = temp_path("merge");
+    let mut tf = TrackFile::create(&path).unwrap();
+    tf.set_reorder_window(1_000_000);
+    // a zero heartbeat keeps every point of the plateau
+    tf.set_dedup_tolerance(DedupConfig {
+      heartbeat: Some(0),
+      ..Default::default()
+    });
+    tf.append_many(&[still(0), still(10_000), still(20_000)])
+      .unwrap();
+    assert_eq!(tf.count().unwrap(), 3);
+
+    // the existing points would be a plateau with the current tolerances
+    tf.set_dedup_tolerance(DedupConfig::default());
+    let report = tf.append_many(&[still(5000)]).unwrap();
+    assert_eq!(report.outcomes, [PointOutcome::Reordered]);
+    assert_eq!(report.grown, 1);
+    assert_eq!(timestamps(&tf), [0, 5000, 10_000, 20_000]);
+    drop(tf);
+
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(timestamps(&tf), [0, 5000, 10_000, 20_000]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn writes_only_append() {
+    let path = temp_path("supersede");
+    let mut tf = TrackFile::create(&path).unwrap();
+    tf.set_reorder_window(1_000_000);
+    let points: Vec<TrackPoint> = (0..200).map(|i| point(i * 1000)).collect();
+    tf.append_many(&points).unwrap();
+    let before = std::fs::read(&path).unwrap();
+
+    // the late point supersedes the blocks from its position on
+    tf.append_many(&[point(100_500)]).unwrap();
+    let after = std::fs::read(&path).unwrap();
+    assert!(after.len() > before.len());
+    assert_eq!(
+      after[Header::SIZE..before.len()],
+      before[Header::SIZE..],
+      "committed bytes were overwritten"
+    );
+    drop(tf);
+
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.count().unwrap(), 201);
+    assert_eq!(tf.verify().unwrap(), None);
+    let stored = timestamps(&tf);
+    assert!(stored.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(stored[101], 100_500);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn truncate_after() {
+    let path = temp_path("truncate");
+    let mut tf = TrackFile::create(&path).unwrap();
+    let points: Vec<TrackPoint> = (0..300).map(|i| point(i * 1000)).collect();
+    tf.append_many(&points).unwrap();
+
+    // within a block, then at the end of the first one
+    assert_eq!(tf.truncate_after(199_500).unwrap(), 100);
+    assert_eq!(tf.count().unwrap(), 200);
+    let boundary = (BLOCK_POINTS as i64 - 1) * 1000;
+    assert_eq!(
+      tf.truncate_after(boundary).unwrap(),
+      200 - BLOCK_POINTS as u64
+    );
+    assert_eq!(tf.truncate_after(i64::MAX).unwrap(), 0);
+    tf.append_many(&[point(500_000)]).unwrap();
+    drop(tf);
+
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.count().unwrap(), BLOCK_POINTS as u64 + 1);
+    assert_eq!(tf.ts_range().unwrap(), Some((0, 500_000)));
+    assert_eq!(tf.verify().unwrap(), None);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn repair_torn_tail() {
+    let path = temp_path("torn");
+    let mut tf = TrackFile::create(&path).unwrap();
+    for i in 0..200 {
+      tf.append_many(&[point(i * 1000)]).unwrap();
+    }
+    drop(tf);
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 3).unwrap();
+    assert!(TrackFile::open(&path).is_err());
+
+    let report = TrackFile::repair(&path).unwrap();
+    assert_eq!(report.header_count, 200);
+    assert!(report.count > 0 && report.count < 200);
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.count().unwrap(), report.count);
+    assert_eq!(tf.verify().unwrap(), None);
+    let expected: Vec<i64> = (0..report.count as i64).map(|i| i * 1000).collect();
+    assert_eq!(timestamps(&tf), expected);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn repair_corrupt_last_block() {
+    let path = temp_path("corrupt");
+    let mut tf = TrackFile::create(&path).unwrap();
+    for i in 0..200 {
+      tf.append_many(&[point(i * 1000)]).unwrap();
+    }
+    drop(tf);
+    let file = OpenOptions::new()
+      .write(true)
+      .read(true)
+      .open(&path)
+      .unwrap();
+    let len = file.metadata().unwrap().len();
+    let mut byte = [0];
+    file.read_exact_at(&mut byte, len - 6).unwrap();
+    file.write_all_at(&[byte[0] ^ 0x55], len - 6).unwrap();
+    assert!(matches!(
+      TrackFile::open(&path),
+      Err(TrackFileError::InvalidBlock(_))
+    ));
+
+    // the superseded copy of the last block still holds the earlier points
+    let report = TrackFile::repair(&path).unwrap();
+    assert_eq!(report.count, 199);
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.verify().unwrap(), None);
+    assert_eq!(tf.ts_range().unwrap(), Some((0, 198_000)));
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn repair_flat_file() {
+    let path = temp_path("flat");
+    let header = Header {
+      version: VERSION_FLAT,
+      flags: 0,
+      count: 3,
+      ..Header::new().unwrap()
+    };
+    let mut data = header.encode();
+    for ts in [0, 1000] {
+      point(ts).encode_into(&mut data);
+    }
+    // a torn third record
+    data.extend_from_slice(&[0; 20]);
+    std::fs::write(&path, &data).unwrap();
+    assert!(matches!(
+      TrackFile::open(&path),
+      Err(TrackFileError::InvalidFileLength(_, _))
+    ));
+
+    let report = TrackFile::repair(&path).unwrap();
+    assert_eq!(report.header_count, 3);
+    assert_eq!(report.count, 2);
+    assert_eq!(report.truncated_bytes, 20);
+    let mut tf = TrackFile::open(&path).unwrap();
+    tf.append_many(&[point(2000)]).unwrap();
+    drop(tf);
+    let tf = TrackFile::open(&path).unwrap();
+    assert_eq!(tf.get_header().unwrap().version(), VERSION_FLAT);
+    assert_eq!(timestamps(&tf), [0, 1000, 2000]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn flat_file_upgraded_for_extended_fields() {
+    let path = temp_path("upgrade");
+    let header = Header {
+      version: VERSION_FLAT,
+      flags: 0,
+      count: 2,
+      ..Header::new().unwrap()
+    };
+    let mut data = header.encode();
+    for ts in [0, 1000] {
+      point(ts).encode_into(&mut data);
+    }
+    std::fs::write(&path, &data).unwrap();
+
+    let extended = TrackPoint {
+      squawk: Some(7700),
+      vs: Some(-1200),
+      ..point(2000)
+    };
+    let mut tf = TrackFile::open(&path).unwrap();
+    let report = tf.append_many(std::slice::from_ref(&extended)).unwrap();
+    assert_eq!(report.outcomes, [PointOutcome::Appended]);
+    drop(tf);
 
-    let data = to_raw(entry);
-    self.file.seek(SeekFrom::End(offset))?;
-    self.file.write_all(&data)?;
-    Ok(!overwrite) // !overwrite means we actually appended a point
+    let tf = TrackFile::open(&path).unwrap();
+    let header = tf.get_header().unwrap();
+    assert_eq!(header.version(), VERSION_BLOCKS);
+    assert!(header.has_checksums());
+    assert_eq!(tf.verify().unwrap(), None);
+    let stored = tf.read_all().unwrap();
+    assert_eq!(timestamps(&tf), [0, 1000, 2000]);
+    assert_eq!(stored[2], quantize(&extended));
+    std::fs::remove_file(&path).unwrap();
   }
 }
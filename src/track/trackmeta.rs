@@ -0,0 +1,46 @@
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+};
+
+use super::{error::TrackFileError, replace::replace_file};
+
+/// Free-form key/value metadata of a track, e.g. callsign, aircraft type,
+/// departure or arrival.
+pub type TrackMeta = BTreeMap<String, String>;
+
+/// Metadata is kept in a JSON sidecar next to the track file
+pub fn meta_path(track_path: &Path) -> PathBuf {
+  track_path.with_extension("meta")
+}
+
+/// Reads the metadata of a track, a missing sidecar means no metadata
+pub fn read_meta(path: &Path) -> Result<TrackMeta, TrackFileError> {
+  let data = match std::fs::read(path) {
+    Ok(data) => data,
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(TrackMeta::new()),
+    Err(err) => return Err(err.into()),
+  };
+  serde_json::from_slice(&data).map_err(|err| {
+    TrackFileError::InvalidMetadata(path.to_string_lossy().to_string(), err.to_string())
+  })
+}
+
+/// Replaces the metadata atomically so that a crash leaves either the old
+/// or the new version on disk
+pub fn write_meta(path: &Path, meta: &TrackMeta) -> Result<(), TrackFileError> {
+  let data = serde_json::to_vec(meta).unwrap();
+  replace_file(path, &data)?;
+  Ok(())
+}
+
+/// Applies an update to the metadata, empty values remove their keys
+pub fn merge_meta(meta: &mut TrackMeta, update: &TrackMeta) {
+  for (key, value) in update.iter() {
+    if value.is_empty() {
+      meta.remove(key);
+    } else {
+      meta.insert(key.clone(), value.clone());
+    }
+  }
+}
@@ -44,7 +44,21 @@ impl From<TrackFileError> for APIError {
         APIError::internal_server_error(Some(format!("error reading track file at index {idx}")))
       }
       TrackFileError::NotFound(msg) => APIError::not_found(&msg),
-      TrackFileError::SequenceError(_) => APIError::bad_request(&format!("{value}")),
+      TrackFileError::InvalidPoint(_, _) | TrackFileError::InvalidTrackId(_) => {
+        APIError::bad_request(&format!("{value}"))
+      }
+      TrackFileError::InvalidField(_, _) => {
+        APIError::internal_server_error(Some(format!("{value}")))
+      }
+      TrackFileError::HeaderChecksumMismatch | TrackFileError::ChecksumMismatch(_) => {
+        APIError::data_corrupted(&format!("{value}"))
+      }
+      TrackFileError::UnsupportedVersion(_)
+      | TrackFileError::InvalidBlock(_)
+      | TrackFileError::CountMismatch(_, _)
+      | TrackFileError::InvalidMetadata(_, _) => {
+        APIError::internal_server_error(Some(format!("{value}")))
+      }
     }
   }
 }
@@ -68,7 +82,11 @@ impl APIError {
   }
 
   pub fn bad_request(message: &str) -> Self {
-    Self::new(403, message)
+    Self::new(400, message)
+  }
+
+  pub fn data_corrupted(message: &str) -> Self {
+    Self::new(500, &format!("DATA CORRUPTED: {message}"))
   }
 
   pub fn internal_server_error(message: Option<String>) -> Self {
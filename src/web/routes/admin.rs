@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use crate::{manager::Manager, web::error::APIError};
+use rocket::{http::Status, post, State};
+
+/// Starts recounting the tracks in the background, the result is logged
+#[post("/reconcile")]
+pub async fn reconcile(manager: &State<Arc<Manager>>) -> Result<Status, APIError> {
+  if manager.start_reconcile() {
+    Ok(Status::Accepted)
+  } else {
+    Err(APIError::new(409, "reconcile is already running"))
+  }
+}
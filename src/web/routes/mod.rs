@@ -1,2 +1,4 @@
+pub mod admin;
+pub mod snapshot;
 pub mod stats;
 pub mod tracks;
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+
+use crate::{manager::Manager, track::entry::TrackPoint, web::error::APIError};
+use rocket::{get, serde::json::Json, State};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct TrackPosition {
+  pub track_id: String,
+  pub point: TrackPoint,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotResponse {
+  pub at: i64,
+  pub tracks: Vec<TrackPosition>,
+  pub count: usize,
+}
+
+/// Positions of all tracks active at `at`
+#[get("/?<at>")]
+pub async fn get_snapshot(
+  at: i64,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<SnapshotResponse>, APIError> {
+  let store = &manager.store;
+  let tracks: Vec<TrackPosition> = store
+    .snapshot(at)
+    .await?
+    .into_iter()
+    .map(|(track_id, point)| TrackPosition { track_id, point })
+    .collect();
+  let count = tracks.len();
+  Ok(Json(SnapshotResponse { at, tracks, count }))
+}
@@ -7,8 +7,16 @@ use rocket::{get, response::content, State};
 pub async fn get_metrics(
   manager: &State<Arc<Manager>>,
 ) -> Result<content::RawText<String>, APIError> {
-  let mut store = manager.store.write().await;
-  let block = store.get_metablock()?;
+  let store = &manager.store;
+  let block = store.get_metablock().await?;
+  let cache = store.cache_stats();
+  let ingest = store.ingest_stats();
+  let lookups = cache.hits + cache.misses;
+  let hit_ratio = if lookups > 0 {
+    cache.hits as f64 / lookups as f64
+  } else {
+    0.0
+  };
 
   let response = format!(
     r#"# HELP tracked_track_count number of tracks currently stored
@@ -18,8 +26,64 @@ tracked_track_count {}
 # HELP tracked_point_count number of points currently stored in all track files
 # TYPE tracked_point_count gauge
 tracked_point_count {}
+
+# HELP tracked_total_bytes size of all track files in bytes
+# TYPE tracked_total_bytes gauge
+tracked_total_bytes {}
+
+# HELP tracked_corrupt_tracks number of corrupt track files found by the last scan
+# TYPE tracked_corrupt_tracks gauge
+tracked_corrupt_tracks {}
+
+# HELP tracked_oldest_track_updated_seconds unix time of the least recent track update as of the last scan
+# TYPE tracked_oldest_track_updated_seconds gauge
+tracked_oldest_track_updated_seconds {}
+
+# HELP tracked_newest_track_updated_seconds unix time of the most recent track update
+# TYPE tracked_newest_track_updated_seconds gauge
+tracked_newest_track_updated_seconds {}
+
+# HELP tracked_reordered_points_total number of late points inserted in order since startup
+# TYPE tracked_reordered_points_total counter
+tracked_reordered_points_total {}
+
+# HELP tracked_dropped_points_total number of points dropped for being older than the reorder window since startup
+# TYPE tracked_dropped_points_total counter
+tracked_dropped_points_total {}
+
+# HELP tracked_cache_size number of track files currently open in the cache
+# TYPE tracked_cache_size gauge
+tracked_cache_size {}
+
+# HELP tracked_cache_capacity maximum number of track files kept open in the cache
+# TYPE tracked_cache_capacity gauge
+tracked_cache_capacity {}
+
+# HELP tracked_cache_hits_total number of track file lookups served from the cache
+# TYPE tracked_cache_hits_total counter
+tracked_cache_hits_total {}
+
+# HELP tracked_cache_misses_total number of track file lookups which opened the file
+# TYPE tracked_cache_misses_total counter
+tracked_cache_misses_total {}
+
+# HELP tracked_cache_hit_ratio ratio of track file lookups served from the cache
+# TYPE tracked_cache_hit_ratio gauge
+tracked_cache_hit_ratio {}
 "#,
-    block.track_count, block.point_count
+    block.track_count,
+    block.point_count,
+    block.total_bytes,
+    block.corrupt_count,
+    block.oldest_updated_at as f64 / 1000.0,
+    block.newest_updated_at as f64 / 1000.0,
+    ingest.reordered,
+    ingest.dropped,
+    cache.size,
+    cache.capacity,
+    cache.hits,
+    cache.misses,
+    hit_ratio
   );
   let response = content::RawText(response);
   Ok(response)
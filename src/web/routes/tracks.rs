@@ -1,16 +1,30 @@
 use crate::{
   manager::Manager,
-  track::entry::{TrackPoint, TrackPointCompact},
+  track::{
+    entry::{TrackPoint, TrackPointCompact},
+    error::TrackFileError,
+    index::TrackSummary,
+    simplify::Simplification,
+    spatial::{BoundingBox, TrackCrossing},
+    trackfile::PointOutcome,
+    trackmeta::TrackMeta,
+  },
   web::error::APIError,
 };
-use rocket::{get, post, serde::json::Json, State};
+use rocket::{delete, get, http::Status, post, response::status::Custom, serde::json::Json, State};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, sync::Arc};
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::Arc,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct PointDef {
   pub track_id: String,
   pub point: TrackPoint,
+  /// metadata to merge into the track's one, empty values remove keys
+  #[serde(default)]
+  pub meta: Option<TrackMeta>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,79 +37,373 @@ pub struct StatusResponse {
   status: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct PointResult {
+  /// position of the point in the request
+  pub index: usize,
+  pub ts: i64,
+  #[serde(flatten)]
+  pub outcome: PointOutcome,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackResult {
+  pub track_id: String,
+  pub points: Vec<PointResult>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub meta_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UpdateTracksResponse {
+  pub status: String,
+  /// number of points stored
+  pub accepted: usize,
+  /// number of points rejected or dropped, sending them again won't help
+  pub rejected: usize,
+  /// number of points and metadata updates failed, these may be retried
+  pub failed: usize,
+  pub tracks: Vec<TrackResult>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TrackResponse {
   pub track_id: String,
+  pub meta: TrackMeta,
   pub points: Vec<TrackPoint>,
   pub count: usize,
 }
 
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+  pub track_id: String,
+  pub ok: bool,
+  pub bad_index: Option<usize>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TruncateResponse {
+  /// number of tracks changed
+  pub tracks: u64,
+  /// number of points removed
+  pub removed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListTracksResponse {
+  pub tracks: Vec<TrackSummary>,
+  /// pass as `cursor` to get the next page, absent on the last one
+  pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+  pub crossings: Vec<TrackCrossing>,
+  pub count: usize,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TrackCompactResponse {
   pub track_id: String,
+  pub meta: TrackMeta,
   pub points: Vec<TrackPointCompact>,
   pub count: usize,
 }
 
+// 200 if all points are stored, 422 if none is stored and there is nothing
+// to retry, 503 if every point failed to be stored and 207 otherwise
+fn update_status(accepted: usize, rejected: usize, failed: usize) -> Status {
+  if rejected == 0 && failed == 0 {
+    Status::Ok
+  } else if accepted == 0 && failed == 0 {
+    Status::UnprocessableEntity
+  } else if accepted == 0 && rejected == 0 {
+    Status::ServiceUnavailable
+  } else {
+    Status::MultiStatus
+  }
+}
+
+/// Applies every point of the request and reports the outcome of each.
+/// Responds with 200 if all points are stored, 422 if none is stored and
+/// there is nothing to retry, 503 if the store failed for every point and
+/// 207 otherwise.
 #[post("/", data = "<req>")]
 pub async fn update_tracks(
   req: Json<UpdateTracksRequest>,
   manager: &State<Arc<Manager>>,
-) -> Result<Json<StatusResponse>, APIError> {
-  let mut ids = HashSet::new();
-  let mut count = 0;
-  let mut store = manager.store.write().await;
-
-  for pdef in req.data.iter() {
-    ids.insert(&pdef.track_id);
-    count += 1;
-    store.append(&pdef.track_id, &pdef.point, true)?;
+) -> Result<Custom<Json<UpdateTracksResponse>>, APIError> {
+  let store = &manager.store;
+  let data = req.into_inner().data;
+  if data.is_empty() {
+    return Err(APIError::bad_request("no points to update"));
   }
 
-  let status = format!("{} points received, {} tracks updated", count, ids.len());
-  Ok(Json(StatusResponse { status }))
+  let mut entries = vec![];
+  let mut metas: BTreeMap<String, TrackMeta> = BTreeMap::new();
+  for pdef in data.into_iter() {
+    if let Some(meta) = pdef.meta {
+      // later updates of a key win, empty values are kept to remove keys
+      metas.entry(pdef.track_id.clone()).or_default().extend(meta);
+    }
+    entries.push((pdef.track_id, pdef.point));
+  }
+  let received = entries.len();
+  let timestamps: Vec<i64> = entries.iter().map(|(_, point)| point.ts).collect();
+  let track_ids: Vec<String> = entries.iter().map(|(id, _)| id.clone()).collect();
+  let outcomes = store.append_batch(entries).await?;
+
+  // tracks are reported in the order of their first point
+  let mut tracks: Vec<TrackResult> = vec![];
+  let mut indices: HashMap<String, usize> = HashMap::new();
+  let (mut accepted, mut rejected, mut failed) = (0, 0, 0);
+  for (index, (track_id, outcome)) in track_ids.into_iter().zip(outcomes).enumerate() {
+    match outcome {
+      PointOutcome::Failed(_) => failed += 1,
+      ref outcome if outcome.is_accepted() => accepted += 1,
+      _ => rejected += 1,
+    }
+    let idx = *indices.entry(track_id.clone()).or_insert_with(|| {
+      tracks.push(TrackResult {
+        track_id,
+        points: vec![],
+        meta_error: None,
+      });
+      tracks.len() - 1
+    });
+    tracks[idx].points.push(PointResult {
+      index,
+      ts: timestamps[index],
+      outcome,
+    });
+  }
+
+  for (track_id, meta) in metas.into_iter() {
+    if let Err(err) = store.update_track_meta(&track_id, meta).await {
+      // the track is missing if none of its points has been stored
+      if !matches!(
+        err,
+        TrackFileError::NotFound(_) | TrackFileError::InvalidTrackId(_)
+      ) {
+        failed += 1;
+      }
+      tracks[indices[&track_id]].meta_error = Some(format!("{err}"));
+    }
+  }
+
+  let updated = tracks
+    .iter()
+    .filter(|track| track.points.iter().any(|p| p.outcome.is_accepted()))
+    .count();
+  let status = format!("{received} points received, {updated} tracks updated");
+  Ok(Custom(
+    update_status(accepted, rejected, failed),
+    Json(UpdateTracksResponse {
+      status,
+      accepted,
+      rejected,
+      failed,
+      tracks,
+    }),
+  ))
+}
+
+const DEFAULT_LIST_LIMIT: usize = 100;
+const MAX_LIST_LIMIT: usize = 1000;
+
+#[get("/?<updated_since>&<prefix>&<limit>&<cursor>")]
+pub async fn list_tracks(
+  updated_since: Option<u64>,
+  prefix: Option<String>,
+  limit: Option<usize>,
+  cursor: Option<String>,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<ListTracksResponse>, APIError> {
+  let store = &manager.store;
+  let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+  let (tracks, next_cursor) = store
+    .list_tracks(prefix, updated_since, limit, cursor)
+    .await?;
+  Ok(Json(ListTracksResponse {
+    tracks,
+    next_cursor,
+  }))
 }
 
-#[get("/<track_id>/json?<interpolate>&<after>")]
+/// Finds the tracks which have been inside the box between `after` and
+/// `before`
+#[get("/search?<min_lat>&<min_lng>&<max_lat>&<max_lng>&<after>&<before>")]
+pub async fn search_tracks(
+  min_lat: f64,
+  min_lng: f64,
+  max_lat: f64,
+  max_lng: f64,
+  after: i64,
+  before: i64,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<SearchResponse>, APIError> {
+  let bbox = BoundingBox {
+    min_lat,
+    min_lng,
+    max_lat,
+    max_lng,
+  };
+  if !bbox.is_valid() {
+    return Err(APIError::bad_request("invalid bounding box"));
+  }
+  if after >= before {
+    return Err(APIError::bad_request("after must be less than before"));
+  }
+  let store = &manager.store;
+  let crossings = store.search_tracks(bbox, after, before).await?;
+  let count = crossings.len();
+  Ok(Json(SearchResponse { crossings, count }))
+}
+
+const DEFAULT_SIMPLIFY_ALT: i32 = 100;
+
+// `simplify` is in meters, `simplify_alt` in ft
+fn simplification(
+  simplify: Option<f64>,
+  simplify_alt: Option<i32>,
+) -> Result<Option<Simplification>, APIError> {
+  let Some(distance) = simplify else {
+    return Ok(None);
+  };
+  let alt = simplify_alt.unwrap_or(DEFAULT_SIMPLIFY_ALT);
+  if !distance.is_finite() || distance < 0.0 || alt < 0 {
+    return Err(APIError::bad_request(
+      "simplification tolerances must be positive",
+    ));
+  }
+  Ok(Some(Simplification { distance, alt }))
+}
+
+#[get("/<track_id>/json?<interpolate>&<after>&<before>&<simplify>&<simplify_alt>")]
 pub async fn show_track(
   track_id: &str,
   interpolate: Option<bool>,
   after: Option<i64>,
+  before: Option<i64>,
+  simplify: Option<f64>,
+  simplify_alt: Option<i32>,
   manager: &State<Arc<Manager>>,
 ) -> Result<Json<TrackResponse>, APIError> {
-  let store = manager.store.read().await;
+  let store = &manager.store;
   let interpolate = interpolate.unwrap_or(false);
-  let points = store.load_track(track_id, interpolate, after)?;
+  let simplification = simplification(simplify, simplify_alt)?;
+  let points = store
+    .load_track(track_id, interpolate, after, before, simplification)
+    .await?;
+  let meta = store.load_track_meta(track_id).await?;
   let count = points.len();
   Ok(Json(TrackResponse {
     track_id: track_id.into(),
+    meta,
     points,
     count,
   }))
 }
 
-#[get("/<track_id>/compact?<interpolate>&<after>")]
+#[get("/<track_id>/compact?<interpolate>&<after>&<before>&<simplify>&<simplify_alt>")]
 pub async fn show_track_compact(
   track_id: &str,
   interpolate: Option<bool>,
   after: Option<i64>,
+  before: Option<i64>,
+  simplify: Option<f64>,
+  simplify_alt: Option<i32>,
   manager: &State<Arc<Manager>>,
 ) -> Result<Json<TrackCompactResponse>, APIError> {
-  let store = manager.store.read().await;
+  let store = &manager.store;
   let interpolate = interpolate.unwrap_or(false);
-  let points = store.load_track_compact(track_id, interpolate, after)?;
-  // TODO: stop reading the entire file when `after` is set
-  let points = if let Some(after) = after {
-    points.into_iter().filter(|p| p.ts > after).collect()
-  } else {
-    points
-  };
-
+  let simplification = simplification(simplify, simplify_alt)?;
+  let points = store
+    .load_track_compact(track_id, interpolate, after, before, simplification)
+    .await?;
+  let meta = store.load_track_meta(track_id).await?;
   let count = points.len();
 
   Ok(Json(TrackCompactResponse {
     track_id: track_id.into(),
+    meta,
     points,
     count,
   }))
 }
+
+#[delete("/<track_id>")]
+pub async fn delete_track(
+  track_id: &str,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<StatusResponse>, APIError> {
+  let store = &manager.store;
+  store.remove_track(track_id).await?;
+  let status = format!("track {track_id} removed");
+  Ok(Json(StatusResponse { status }))
+}
+
+#[post("/<track_id>/truncate?<after>")]
+pub async fn truncate_track(
+  track_id: &str,
+  after: i64,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<TruncateResponse>, APIError> {
+  let store = &manager.store;
+  let removed = store.truncate_track(track_id, after).await?;
+  Ok(Json(TruncateResponse {
+    tracks: (removed > 0).into(),
+    removed,
+  }))
+}
+
+/// Truncates every track updated since `since` (defaults to `after`)
+#[post("/truncate?<after>&<since>")]
+pub async fn truncate_tracks(
+  after: i64,
+  since: Option<u64>,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<TruncateResponse>, APIError> {
+  let store = &manager.store;
+  let since = since.unwrap_or(after.max(0) as u64);
+  let (tracks, removed) = store.truncate_tracks(since, after).await?;
+  Ok(Json(TruncateResponse { tracks, removed }))
+}
+
+#[get("/<track_id>/verify")]
+pub async fn verify_track(
+  track_id: &str,
+  manager: &State<Arc<Manager>>,
+) -> Result<Json<VerifyResponse>, APIError> {
+  let store = &manager.store;
+  let (bad_index, error) = match store.verify_track(track_id).await {
+    Ok(bad_index) => (bad_index, None),
+    Err(err @ (TrackFileError::NotFound(_) | TrackFileError::IOError(_))) => {
+      return Err(err.into())
+    }
+    Err(err) => (None, Some(format!("{err}"))),
+  };
+
+  Ok(Json(VerifyResponse {
+    track_id: track_id.into(),
+    ok: bad_index.is_none() && error.is_none(),
+    bad_index,
+    error,
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn update_status_codes() {
+    assert_eq!(update_status(3, 0, 0), Status::Ok);
+    assert_eq!(update_status(0, 3, 0), Status::UnprocessableEntity);
+    assert_eq!(update_status(0, 0, 3), Status::ServiceUnavailable);
+    assert_eq!(update_status(2, 1, 0), Status::MultiStatus);
+    assert_eq!(update_status(2, 0, 1), Status::MultiStatus);
+    assert_eq!(update_status(0, 2, 1), Status::MultiStatus);
+  }
+}